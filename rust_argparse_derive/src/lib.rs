@@ -0,0 +1,562 @@
+//! `#[derive(ArgParse)]` for `rust_argparse`, enabled through its `derive`
+//! cargo feature.
+//!
+//! Structs turn their fields into arguments, keyed by the field name:
+//!
+//! * `bool` fields become flags,
+//! * `Option<T>` fields become optionals, or positionals taking at most one
+//!   token when marked `positional`, both `None` unless given a `default`,
+//! * `Vec<T>` fields become optionals collecting every occurrence, or
+//!   positionals taking any number of tokens when marked `positional`,
+//! * integer fields marked `count` become flags counting their occurrences
+//!   (the field must be a `u8`),
+//! * fields with a `short`, `long`, `default` or `env` attribute become
+//!   optionals, required unless they have a `default`,
+//! * `[T; N]` fields take exactly `N` tokens, as a positional or an optional,
+//! * fields marked `subcommand` add the sub-actions of their (enum) type,
+//! * every other field becomes a positional.
+//!
+//! Enums turn every variant into a sub-action named after the variant in
+//! kebab-case, with further names given as `alias = "..."`. Unit variants
+//! take no arguments, newtype variants take the arguments of the wrapped type
+//! and struct variants take their own fields. The sub-actions inherit the
+//! main of their parent, so a derived tree needs one main, on its root.
+//!
+//! ```
+//! use rust_argparse::command_line_parsing_results::CmdParsingResults;
+//! use rust_argparse::{ArgParse, Parser};
+//!
+//! /// manages services
+//! #[derive(ArgParse)]
+//! #[argparse(name = "tool")]
+//! struct Opts {
+//!     /// shutdown timeout
+//!     #[argparse(short = 't', default = "10")]
+//!     timeout: u64,
+//!     #[argparse(subcommand)]
+//!     command: Command,
+//! }
+//!
+//! #[derive(ArgParse)]
+//! enum Command {
+//!     /// starts a service
+//!     Start { service: String },
+//!     /// lists all services
+//!     List,
+//! }
+//!
+//! let parser: Parser<dyn FnOnce(&CmdParsingResults) -> Result<(), String>> = Opts::parser()
+//!     .with_main(|results: &CmdParsingResults| {
+//!         let opts = Opts::from_results(results);
+//!         // ...
+//!         Ok(())
+//!     });
+//! let args = ["-t", "5", "start", "web"].map(String::from).to_vec();
+//! let (results, main) = parser.parse(args).unwrap();
+//! let opts = Opts::from_results(&results);
+//! assert_eq!(opts.timeout, 5);
+//! assert!(matches!(opts.command, Command::Start { service } if service == "web"));
+//! main(&results).unwrap();
+//! ```
+//!
+//! The short name of an optional or flag defaults to the first letter of its
+//! key. Two switches of a struct with the same short, or a short `h`, which
+//! requests the help, fail to compile; give one of them another `short`:
+//!
+//! ```compile_fail
+//! use rust_argparse::ArgParse;
+//!
+//! #[derive(ArgParse)]
+//! struct Opts {
+//!     verbose: bool,
+//!     version: bool, // -v is taken by `verbose`
+//! }
+//! ```
+//!
+//! Optionals and flags marked `env = "VAR"` fall back to that environment
+//! variable when not given on the command line.
+//!
+//! Field values are converted with `FromStr` and read back with `Clone`.
+//! Docs come from `///` comments unless given as `doc = "..."`. Besides
+//! `ArgParse` the derive implements `FromResults`, so
+//! `results.extract::<T>()` reports every missing or mistyped value.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::{
+    Attribute, Data, DeriveInput, Expr, ExprLit, Field, Fields, GenericArgument, Ident, Lit,
+    LitChar, LitStr, Meta, MetaNameValue, PathArguments, Type, parse_macro_input,
+};
+
+#[proc_macro_derive(ArgParse, attributes(argparse))]
+pub fn derive_arg_parse(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+/// The content of the `#[argparse(...)]` and doc attributes of an item.
+#[derive(Default)]
+struct ArgAttributes {
+    name: Option<String>,
+    aliases: Vec<String>,
+    short: Option<char>,
+    long: Option<String>,
+    default: Option<String>,
+    env: Option<String>,
+    doc: Option<String>,
+    positional: bool,
+    subcommand: bool,
+    count: bool,
+}
+
+impl ArgAttributes {
+    fn from_attributes(attrs: &[Attribute]) -> syn::Result<ArgAttributes> {
+        let mut parsed = ArgAttributes::default();
+        let mut doc_lines: Vec<String> = Vec::new();
+        for attr in attrs {
+            if attr.path().is_ident("doc") {
+                if let Meta::NameValue(MetaNameValue {
+                    value:
+                        Expr::Lit(ExprLit {
+                            lit: Lit::Str(line),
+                            ..
+                        }),
+                    ..
+                }) = &attr.meta
+                {
+                    let line = line.value().trim().to_string();
+                    if !line.is_empty() {
+                        doc_lines.push(line);
+                    }
+                }
+            } else if attr.path().is_ident("argparse") {
+                attr.parse_nested_meta(|meta| {
+                    if meta.path.is_ident("name") {
+                        parsed.name = Some(meta.value()?.parse::<LitStr>()?.value());
+                    } else if meta.path.is_ident("alias") {
+                        parsed
+                            .aliases
+                            .push(meta.value()?.parse::<LitStr>()?.value());
+                    } else if meta.path.is_ident("short") {
+                        parsed.short = Some(meta.value()?.parse::<LitChar>()?.value());
+                    } else if meta.path.is_ident("long") {
+                        parsed.long = Some(meta.value()?.parse::<LitStr>()?.value());
+                    } else if meta.path.is_ident("default") {
+                        parsed.default = Some(meta.value()?.parse::<LitStr>()?.value());
+                    } else if meta.path.is_ident("env") {
+                        parsed.env = Some(meta.value()?.parse::<LitStr>()?.value());
+                    } else if meta.path.is_ident("doc") {
+                        parsed.doc = Some(meta.value()?.parse::<LitStr>()?.value());
+                    } else if meta.path.is_ident("positional") {
+                        parsed.positional = true;
+                    } else if meta.path.is_ident("subcommand") {
+                        parsed.subcommand = true;
+                    } else if meta.path.is_ident("count") {
+                        parsed.count = true;
+                    } else {
+                        return Err(meta.error("unknown argparse attribute"));
+                    }
+                    Ok(())
+                })?;
+            }
+        }
+        if parsed.doc.is_none() {
+            parsed.doc = Some(doc_lines.join(" "));
+        }
+        Ok(parsed)
+    }
+
+    fn doc(&self) -> String {
+        self.doc.clone().unwrap_or_default()
+    }
+}
+
+fn expand(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let ident = &input.ident;
+    let attrs = ArgAttributes::from_attributes(&input.attrs)?;
+    let name = attrs
+        .name
+        .clone()
+        .unwrap_or_else(|| kebab_case(&ident.to_string()));
+    let doc = attrs.doc();
+    let (augment, extract) = match &input.data {
+        Data::Struct(data) => {
+            let (augments, extract) = expand_fields(&data.fields, quote!(#ident))?;
+            (quote!(#(#augments)* parser), extract)
+        }
+        Data::Enum(data) => {
+            let mut actions = Vec::new();
+            let mut arms = Vec::new();
+            let mut names = Vec::new();
+            for variant in data.variants.iter() {
+                let variant_ident = &variant.ident;
+                let variant_attrs = ArgAttributes::from_attributes(&variant.attrs)?;
+                let action_name = variant_attrs
+                    .name
+                    .clone()
+                    .unwrap_or_else(|| kebab_case(&variant_ident.to_string()));
+                let action_doc = variant_attrs.doc();
+                names.push(action_name.clone());
+                let aliases = &variant_attrs.aliases;
+                let new_parser = quote! {
+                    ::rust_argparse::Parser::new(#action_name, #action_doc)
+                        #(.alias(#aliases))*
+                        .inherit_main()
+                };
+                match &variant.fields {
+                    Fields::Unnamed(fields) if fields.unnamed.len() == 1 => {
+                        let ty = &fields.unnamed[0].ty;
+                        actions.push(quote! {
+                            let parser = parser.add_action(
+                                <#ty as ::rust_argparse::ArgParse>::augment_parser(#new_parser),
+                            );
+                        });
+                        arms.push(quote! {
+                            #action_name => return results.extract::<#ty>().map(#ident::#variant_ident),
+                        });
+                    }
+                    Fields::Unnamed(fields) => {
+                        return Err(syn::Error::new_spanned(
+                            fields,
+                            "ArgParse variants wrap exactly one type",
+                        ));
+                    }
+                    fields => {
+                        let (augments, extract) =
+                            expand_fields(fields, quote!(#ident::#variant_ident))?;
+                        actions.push(quote! {
+                            let parser = parser.add_action({
+                                let parser = #new_parser;
+                                #(#augments)*
+                                parser
+                            });
+                        });
+                        arms.push(quote!(#action_name => return #extract,));
+                    }
+                }
+            }
+            let augment = quote!(#(#actions)* parser);
+            let extract = quote! {
+                for action in results.get_action_path().iter().skip(1) {
+                    match action.as_str() {
+                        #(#arms)*
+                        _ => {}
+                    }
+                }
+                Err(::rust_argparse::command_line_parsing_results::ValueError::MissingAction {
+                    actions: vec![#(#names.to_string()),*],
+                }
+                .into())
+            };
+            (augment, extract)
+        }
+        Data::Union(_) => {
+            return Err(syn::Error::new_spanned(
+                input,
+                "ArgParse can only be derived for structs and enums",
+            ));
+        }
+    };
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::rust_argparse::ArgParse for #ident #ty_generics #where_clause {
+            const NAME: &'static str = #name;
+            const DOC: &'static str = #doc;
+
+            fn augment_parser<F: ?Sized + 'static>(
+                parser: ::rust_argparse::Parser<F>,
+            ) -> ::rust_argparse::Parser<F> {
+                #augment
+            }
+        }
+
+        impl #impl_generics ::rust_argparse::command_line_parsing_results::FromResults
+            for #ident #ty_generics #where_clause
+        {
+            fn try_from_results(
+                results: &::rust_argparse::command_line_parsing_results::CmdParsingResults,
+            ) -> ::std::result::Result<
+                Self,
+                ::rust_argparse::command_line_parsing_results::ExtractError,
+            > {
+                #extract
+            }
+        }
+    })
+}
+
+/// Returns the builder statements for all `fields` and the block extracting
+/// `constructor` from the results.
+fn expand_fields(
+    fields: &Fields,
+    constructor: TokenStream2,
+) -> syn::Result<(Vec<TokenStream2>, TokenStream2)> {
+    match fields {
+        Fields::Named(named) => {
+            let mut augments = Vec::new();
+            let mut reads = Vec::new();
+            let mut inits = Vec::new();
+            let mut shorts = Vec::new();
+            for field in named.named.iter() {
+                let (augment, read, init) = expand_field(field, &mut shorts)?;
+                augments.push(augment);
+                reads.push(read);
+                inits.push(init);
+            }
+            let extract = quote! {{
+                let mut extractor = results.extractor();
+                #(#reads)*
+                extractor.finish()?;
+                Ok(#constructor { #(#inits),* })
+            }};
+            Ok((augments, extract))
+        }
+        Fields::Unit => Ok((Vec::new(), quote!(Ok(#constructor)))),
+        Fields::Unnamed(unnamed) => Err(syn::Error::new_spanned(
+            unnamed,
+            "ArgParse needs named fields to name the arguments",
+        )),
+    }
+}
+
+/// Returns the builder statement of `field`, the statement reading it through
+/// the `extractor` and its initializer in the constructor. `shorts` holds the
+/// short names taken by the previous fields, with their keys.
+fn expand_field(
+    field: &Field,
+    shorts: &mut Vec<(char, String)>,
+) -> syn::Result<(TokenStream2, TokenStream2, TokenStream2)> {
+    let ident: &Ident = field.ident.as_ref().expect("fields are named");
+    let local = format_ident!("arg_{}", ident);
+    let required = quote!(#ident: #local.expect("checked by finish"));
+    let ty = &field.ty;
+    let attrs = ArgAttributes::from_attributes(&field.attrs)?;
+    if attrs.subcommand {
+        return Ok((
+            quote!(let parser = <#ty as ::rust_argparse::ArgParse>::augment_parser(parser);),
+            quote!(let #local = extractor.extract::<#ty>();),
+            required,
+        ));
+    }
+    let key = attrs
+        .name
+        .clone()
+        .unwrap_or_else(|| ident.to_string().trim_start_matches("r#").to_string());
+    let doc = attrs.doc();
+    let long = attrs.long.clone().unwrap_or_else(|| key.replace('_', "-"));
+    let short = attrs
+        .short
+        .unwrap_or_else(|| key.chars().next().expect("names are not empty"));
+    if attrs.positional && attrs.env.is_some() {
+        return Err(syn::Error::new_spanned(
+            field,
+            "env applies to optionals and flags, not positionals",
+        ));
+    }
+    let env = attrs.env.as_ref().map(|var| quote!(.env(#var)));
+    let is_optional = attrs.short.is_some()
+        || attrs.long.is_some()
+        || attrs.default.is_some()
+        || attrs.env.is_some();
+    let is_switch = is_bool(ty)
+        || attrs.count
+        || (!attrs.positional
+            && (generic_inner(ty, "Vec").is_some()
+                || generic_inner(ty, "Option").is_some()
+                || is_optional));
+    if is_switch {
+        claim_short(field, &key, short, shorts)?;
+    }
+    if is_bool(ty) {
+        return Ok((
+            quote!(let parser = parser.add_flag(#key, #long, #short, #doc)#env;),
+            quote!(let #local = extractor.value::<bool>(#key);),
+            required,
+        ));
+    }
+    if attrs.count {
+        return Ok((
+            quote!(let parser = parser.add_flag(#key, #long, #short, #doc).count()#env;),
+            quote!(let #local = extractor.value::<u8>(#key);),
+            required,
+        ));
+    }
+    let default = match &attrs.default {
+        Some(default) => quote!(Some(#default)),
+        None => quote!(None),
+    };
+    let default_value = attrs
+        .default
+        .as_ref()
+        .map(|default| quote!(.default_value(#default)));
+    if let Some(inner) = generic_inner(ty, "Vec")
+        && attrs.positional
+    {
+        return Ok((
+            quote! {
+                let parser = parser
+                    .add_typed_positional::<#inner>(#key, #doc)
+                    .nargs(::rust_argparse::nargs::Nargs::ZeroOrMore);
+            },
+            quote!(let #local = extractor.values::<#inner>(#key);),
+            required,
+        ));
+    }
+    if let Some(inner) = generic_inner(ty, "Option")
+        && attrs.positional
+    {
+        return Ok((
+            quote! {
+                let parser = parser
+                    .add_typed_positional::<#inner>(#key, #doc)
+                    .nargs(::rust_argparse::nargs::Nargs::Optional)
+                    #default_value;
+            },
+            quote!(let #local = extractor.optional_value::<#inner>(#key);),
+            quote!(#ident: #local),
+        ));
+    }
+    if let Some(inner) = generic_inner(ty, "Vec") {
+        return Ok((
+            quote! {
+                let parser = parser
+                    .add_typed_optional::<#inner>(#key, #long, #short, #default, #doc)
+                    .append()
+                    #env;
+            },
+            quote!(let #local = extractor.values::<#inner>(#key);),
+            required,
+        ));
+    }
+    if let Some(inner) = generic_inner(ty, "Option") {
+        return Ok((
+            quote! {
+                let parser = parser.add_typed_optional::<#inner>(#key, #long, #short, #default, #doc)#env;
+            },
+            quote!(let #local = extractor.optional_value::<#inner>(#key);),
+            quote!(#ident: #local),
+        ));
+    }
+    // arrays take exactly as many tokens as they have elements
+    let (value_ty, nargs, read) = match ty {
+        Type::Array(array) => {
+            let elem = &array.elem;
+            let len = &array.len;
+            (
+                quote!(#elem),
+                quote!(.nargs(::rust_argparse::nargs::Nargs::Exactly(#len))),
+                quote!(let #local = extractor.array::<#elem, { #len }>(#key);),
+            )
+        }
+        _ => (
+            quote!(#ty),
+            TokenStream2::new(),
+            quote!(let #local = extractor.value::<#ty>(#key);),
+        ),
+    };
+    if attrs.positional || !is_optional {
+        return Ok((
+            quote!(let parser = parser.add_typed_positional::<#value_ty>(#key, #doc)#nargs;),
+            read,
+            required,
+        ));
+    }
+    // without a default there is nothing to fill the field with
+    let mandatory = match attrs.default {
+        Some(_) => TokenStream2::new(),
+        None => quote!(.required()),
+    };
+    Ok((
+        quote! {
+            let parser = parser
+                .add_typed_optional::<#value_ty>(#key, #long, #short, #default, #doc)
+                #nargs
+                #env
+                #mandatory;
+        },
+        read,
+        required,
+    ))
+}
+
+/// Records `short` as taken by the switch `key`. Fails for `-h`, which
+/// requests the help, and for a short taken by a previous field.
+fn claim_short(
+    field: &Field,
+    key: &str,
+    short: char,
+    shorts: &mut Vec<(char, String)>,
+) -> syn::Result<()> {
+    if short == 'h' {
+        return Err(syn::Error::new_spanned(
+            field,
+            format!(
+                "short -h of `{}` is taken by the help, set another `short`",
+                key
+            ),
+        ));
+    }
+    if let Some((_, other)) = shorts.iter().find(|(taken, _)| *taken == short) {
+        return Err(syn::Error::new_spanned(
+            field,
+            format!(
+                "short -{} of `{}` is already taken by `{}`, set another `short`",
+                short, key, other
+            ),
+        ));
+    }
+    shorts.push((short, key.to_string()));
+    Ok(())
+}
+
+fn last_segment_is(ty: &Type, name: &str) -> bool {
+    match ty {
+        Type::Path(path) => path
+            .path
+            .segments
+            .last()
+            .is_some_and(|segment| segment.ident == name),
+        _ => false,
+    }
+}
+
+fn is_bool(ty: &Type) -> bool {
+    last_segment_is(ty, "bool")
+}
+
+/// The `T` of `ty` if it is a `wrapper<T>`, e.g. an `Option<T>`.
+fn generic_inner<'a>(ty: &'a Type, wrapper: &str) -> Option<&'a Type> {
+    if !last_segment_is(ty, wrapper) {
+        return None;
+    }
+    let Type::Path(path) = ty else { return None };
+    match &path.path.segments.last()?.arguments {
+        PathArguments::AngleBracketed(arguments) => match arguments.args.first()? {
+            GenericArgument::Type(inner) => Some(inner),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// `StartService` becomes `start-service`.
+fn kebab_case(name: &str) -> String {
+    let mut kebab = String::new();
+    for (index, c) in name.chars().enumerate() {
+        if c.is_uppercase() {
+            if index != 0 {
+                kebab.push('-');
+            }
+            kebab.extend(c.to_lowercase());
+        } else if c == '_' {
+            kebab.push('-');
+        } else {
+            kebab.push(c);
+        }
+    }
+    kebab
+}
@@ -0,0 +1,356 @@
+use rust_argparse::ArgParse;
+use rust_argparse::Parser;
+use rust_argparse::command_line_parsing_results::{CmdParsingResults, ValueError};
+use rust_argparse::parse_error::ParseError;
+
+type ToolAction = dyn FnOnce(&CmdParsingResults) -> Result<String, String>;
+
+/// deploys the app
+#[derive(ArgParse, Debug, PartialEq)]
+struct DeployOpts {
+    /// deployment target
+    target: String,
+    /// number of replicas
+    #[argparse(short = 'r', default = "1")]
+    replicas: u32,
+    /// optional region
+    #[argparse(short = 'g')]
+    region: Option<String>,
+    /// print more
+    verbose: bool,
+}
+
+#[derive(ArgParse, Debug, PartialEq)]
+struct StartOpts {
+    /// service name
+    service: String,
+    /// force start
+    force: bool,
+}
+
+/// manages services
+#[derive(ArgParse, Debug, PartialEq)]
+#[argparse(name = "tool")]
+struct ToolOpts {
+    /// log level
+    #[argparse(long = "log-level", short = 'l', default = "info")]
+    log_level: String,
+    #[argparse(subcommand)]
+    command: Command,
+}
+
+#[derive(ArgParse, Debug, PartialEq)]
+enum Command {
+    /// starts the service
+    Start(StartOpts),
+    /// stops the service
+    #[argparse(alias = "stop")]
+    StopService {
+        /// service name
+        service: String,
+        /// shutdown timeout
+        #[argparse(short = 't', default = "10")]
+        timeout: u64,
+    },
+    /// lists all services
+    List,
+}
+
+fn args(tokens: &[&str]) -> Vec<String> {
+    tokens.iter().map(|token| token.to_string()).collect()
+}
+
+#[test]
+fn derived_struct_builds_parser_and_reads_results() {
+    let parser: Parser<ToolAction> =
+        DeployOpts::parser().with_main(|_: &CmdParsingResults| Ok("deployed".to_string()));
+    let (results, main) = parser
+        .parse(args(&["production", "-r", "3", "--verbose"]))
+        .unwrap();
+    assert_eq!(
+        DeployOpts::from_results(&results),
+        DeployOpts {
+            target: "production".to_string(),
+            replicas: 3,
+            region: None,
+            verbose: true,
+        }
+    );
+    assert_eq!(main(&results), Ok("deployed".to_string()));
+}
+
+#[derive(ArgParse, Debug, PartialEq)]
+struct RegionOpts {
+    /// cloud region
+    #[argparse(short = 'g', default = "eu")]
+    region: Option<String>,
+    /// availability zone
+    #[argparse(positional, default = "a")]
+    zone: Option<String>,
+}
+
+#[test]
+fn derived_option_fields_keep_their_default() {
+    let parser: Parser<ToolAction> =
+        RegionOpts::parser().with_main(|_: &CmdParsingResults| Ok(String::new()));
+    let (results, _) = parser.parse(Vec::new()).unwrap();
+    assert_eq!(
+        RegionOpts::from_results(&results),
+        RegionOpts {
+            region: Some("eu".to_string()),
+            zone: Some("a".to_string()),
+        }
+    );
+}
+
+#[test]
+fn derived_struct_uses_name_and_docs_in_help() {
+    let parser: Parser<ToolAction> = DeployOpts::parser();
+    let expected = r#"deploy-opts - deploys the app
+
+usage: deploy-opts [target] {-r,-g,-v}
+
+[target]              deployment target
+-r,--replicas         number of replicas
+-g,--region           optional region
+-v,--verbose          print more
+"#;
+    assert_eq!(parser.help(), expected);
+}
+
+#[test]
+fn derived_enum_adds_sub_actions_sharing_the_root_main() {
+    let build = || -> Parser<ToolAction> {
+        ToolOpts::parser().with_main(|results: &CmdParsingResults| {
+            Ok(format!("{:?}", ToolOpts::from_results(results).command))
+        })
+    };
+
+    let (results, main) = build().parse(args(&["start", "web", "-f"])).unwrap();
+    assert_eq!(
+        ToolOpts::from_results(&results),
+        ToolOpts {
+            log_level: "info".to_string(),
+            command: Command::Start(StartOpts {
+                service: "web".to_string(),
+                force: true,
+            }),
+        }
+    );
+    assert_eq!(
+        main(&results),
+        Ok("Start(StartOpts { service: \"web\", force: true })".to_string())
+    );
+
+    let (results, _) = build()
+        .parse(args(&["-l", "debug", "stop-service", "db", "-t", "30"]))
+        .unwrap();
+    assert_eq!(
+        ToolOpts::from_results(&results),
+        ToolOpts {
+            log_level: "debug".to_string(),
+            command: Command::StopService {
+                service: "db".to_string(),
+                timeout: 30,
+            },
+        }
+    );
+
+    let (results, _) = build().parse(args(&["list"])).unwrap();
+    assert_eq!(ToolOpts::from_results(&results).command, Command::List);
+}
+
+#[test]
+fn derived_typed_field_reports_bad_values() {
+    let parser: Parser<ToolAction> = DeployOpts::parser();
+    let error = parser
+        .parse(args(&["production", "-r", "many"]))
+        .err()
+        .expect("should not have parsed");
+    assert_eq!(error.argument(), Some("replicas"));
+    assert_eq!(error.position(), Some(2));
+}
+
+#[test]
+fn derived_extract_lists_every_missing_value() {
+    let parser: Parser<ToolAction> = Parser::new("other", "an unrelated parser")
+        .add_positional("target", "deployment target")
+        .add_optional("replicas", "replicas", 'r', Some("1"), "number of replicas")
+        .with_main(|_: &CmdParsingResults| Ok(String::new()));
+    let (results, _) = parser.parse(args(&["production"])).unwrap();
+    let error = results.extract::<DeployOpts>().unwrap_err();
+    assert_eq!(
+        error.errors(),
+        [
+            ValueError::WrongType {
+                name: "replicas".to_string(),
+                expected: "u32",
+                found: Some("alloc::string::String"),
+            },
+            ValueError::Missing {
+                name: "verbose".to_string()
+            },
+        ]
+    );
+}
+
+#[test]
+fn derived_enum_extract_without_action_fails() {
+    let parser: Parser<ToolAction> =
+        Parser::new("tool", "doc").with_main(|_: &CmdParsingResults| Ok(String::new()));
+    let (results, _) = parser.parse(args(&[])).unwrap();
+    assert_eq!(
+        results.extract::<Command>().unwrap_err().errors(),
+        [ValueError::MissingAction {
+            actions: vec![
+                "start".to_string(),
+                "stop-service".to_string(),
+                "list".to_string()
+            ],
+        }]
+    );
+}
+
+#[derive(ArgParse, Debug, PartialEq)]
+struct BuildOpts {
+    /// include paths
+    #[argparse(short = 'I')]
+    include: Vec<String>,
+    /// verbosity
+    #[argparse(count)]
+    verbose: u8,
+}
+
+#[test]
+fn derived_vec_and_count_fields_collect_occurrences() {
+    let parser: Parser<ToolAction> =
+        BuildOpts::parser().with_main(|_: &CmdParsingResults| Ok(String::new()));
+    let (results, _) = parser
+        .parse(args(&["-v", "-I", "src", "-v", "--include", "lib"]))
+        .unwrap_or_else(|e| panic!("{}", e));
+    assert_eq!(
+        BuildOpts::from_results(&results),
+        BuildOpts {
+            include: vec!["src".to_string(), "lib".to_string()],
+            verbose: 2,
+        }
+    );
+}
+
+#[derive(ArgParse, Debug, PartialEq)]
+struct CatOpts {
+    /// output file
+    #[argparse(positional)]
+    output: Option<String>,
+    /// input files
+    #[argparse(positional)]
+    inputs: Vec<String>,
+}
+
+#[test]
+fn derived_positional_vec_and_option_take_variable_tokens() {
+    let parser: Parser<ToolAction> =
+        CatOpts::parser().with_main(|_: &CmdParsingResults| Ok(String::new()));
+    let (results, _) = parser.parse(args(&["out", "a", "b"])).unwrap();
+    assert_eq!(
+        CatOpts::from_results(&results),
+        CatOpts {
+            output: Some("out".to_string()),
+            inputs: vec!["a".to_string(), "b".to_string()],
+        }
+    );
+    assert_eq!(
+        CatOpts::parser::<ToolAction>().help(),
+        "cat-opts - \n\nusage: cat-opts [output?] [inputs*]\n\n[output?]             output file\n[inputs*]             input files\n"
+    );
+}
+
+#[derive(ArgParse, Debug, PartialEq)]
+struct PlotOpts {
+    /// x range
+    #[argparse(short = 'x', default = "0 1")]
+    range: [f64; 2],
+    /// point to mark
+    point: [i32; 2],
+}
+
+#[test]
+fn derived_array_fields_take_fixed_counts() {
+    let parser: Parser<ToolAction> =
+        PlotOpts::parser().with_main(|_: &CmdParsingResults| Ok(String::new()));
+    let (results, _) = parser.parse(args(&["3", "-x", "-1", "2", "4"])).unwrap();
+    assert_eq!(
+        PlotOpts::from_results(&results),
+        PlotOpts {
+            range: [-1.0, 2.0],
+            point: [3, 4],
+        }
+    );
+}
+
+#[test]
+fn derived_variant_alias_selects_the_variant() {
+    let parser: Parser<ToolAction> =
+        ToolOpts::parser().with_main(|_: &CmdParsingResults| Ok(String::new()));
+    let (results, _) = parser.parse(args(&["stop", "db"])).unwrap();
+    assert_eq!(
+        ToolOpts::from_results(&results).command,
+        Command::StopService {
+            service: "db".to_string(),
+            timeout: 10,
+        }
+    );
+}
+
+#[derive(ArgParse, Debug, PartialEq)]
+struct LoginOpts {
+    /// user name
+    #[argparse(short = 'u')]
+    user: String,
+    /// api key
+    #[argparse(long = "api-key")]
+    key: String,
+}
+
+#[test]
+fn derived_optional_without_default_is_required() {
+    let parser: Parser<ToolAction> =
+        LoginOpts::parser().with_main(|_: &CmdParsingResults| Ok(String::new()));
+    match parser.parse(args(&[])) {
+        Err(ParseError::MissingRequired { arguments, .. }) => {
+            assert_eq!(arguments, ["-u/--user", "-k/--api-key"])
+        }
+        Err(e) => panic!("unexpected error {:?}", e),
+        Ok(_) => panic!("should not have parsed"),
+    }
+}
+
+#[derive(ArgParse, Debug, PartialEq)]
+struct ServeOpts {
+    /// tcp port
+    #[argparse(env = "DERIVE_SERVE_PORT", default = "8080")]
+    port: u16,
+    /// run in the background
+    #[argparse(env = "DERIVE_SERVE_DAEMON")]
+    daemon: bool,
+}
+
+#[test]
+fn derived_env_attribute_falls_back_to_environment() {
+    let parser: Parser<ToolAction> = ServeOpts::parser()
+        .with_env_lookup(|var| match var {
+            "DERIVE_SERVE_PORT" => Some("9000".to_string()),
+            "DERIVE_SERVE_DAEMON" => Some("true".to_string()),
+            _ => None,
+        })
+        .with_main(|_: &CmdParsingResults| Ok(String::new()));
+    let (results, _) = parser.parse(args(&[])).unwrap();
+    assert_eq!(
+        ServeOpts::from_results(&results),
+        ServeOpts {
+            port: 9000,
+            daemon: true
+        }
+    );
+    assert!(parser.help().contains("tcp port [env: DERIVE_SERVE_PORT]"));
+}
@@ -0,0 +1,28 @@
+/// Arguments listed together under a heading in the help.
+pub(crate) struct ArgumentGroup {
+    title: String,
+    description: String,
+    members: Vec<String>,
+}
+
+impl ArgumentGroup {
+    pub(crate) fn new(title: String, description: String, members: Vec<String>) -> ArgumentGroup {
+        ArgumentGroup {
+            title,
+            description,
+            members,
+        }
+    }
+
+    pub(crate) fn title(&self) -> &str {
+        &self.title
+    }
+
+    pub(crate) fn description(&self) -> &str {
+        &self.description
+    }
+
+    pub(crate) fn contains(&self, name: &str) -> bool {
+        self.members.iter().any(|member| member == name)
+    }
+}
@@ -0,0 +1,78 @@
+use crate::parse_error::ParseError;
+
+/// The tokens a positional or optional accepts; unrestricted if empty.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Choices {
+    values: Vec<String>,
+}
+
+impl Choices {
+    pub(crate) fn new(values: &[&str]) -> Choices {
+        Choices {
+            values: values.iter().map(|value| value.to_string()).collect(),
+        }
+    }
+
+    /// Fails with [`ParseError::InvalidChoice`] if `token` is not one of the
+    /// values. Checked before conversion, so the values are compared as given.
+    pub(crate) fn check(
+        &self,
+        argument: &str,
+        token: &str,
+        position: Option<usize>,
+    ) -> Result<(), ParseError> {
+        if !self.is_restricted() || self.values.iter().any(|value| value == token) {
+            return Ok(());
+        }
+        Err(ParseError::InvalidChoice {
+            argument: argument.to_string(),
+            value: token.to_string(),
+            choices: self.values.clone(),
+            position,
+            context: Box::default(),
+        })
+    }
+
+    pub(crate) fn values(&self) -> &[String] {
+        &self.values
+    }
+
+    pub(crate) fn is_restricted(&self) -> bool {
+        !self.values.is_empty()
+    }
+
+    /// The marker appended to an argument's name in usage and help, e.g.
+    /// ` {debug,info,warn}`.
+    pub(crate) fn suffix(&self) -> String {
+        if !self.is_restricted() {
+            String::new()
+        } else {
+            format!(" {{{}}}", self.values.join(","))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn check_accepts_listed_values_only() {
+        let choices = Choices::new(&["debug", "info"]);
+        assert!(choices.check("level", "info", Some(1)).is_ok());
+        match choices.check("level", "trace", Some(1)) {
+            Err(ParseError::InvalidChoice {
+                value,
+                choices,
+                position,
+                ..
+            }) => {
+                assert_eq!(value, "trace");
+                assert_eq!(choices, ["debug", "info"]);
+                assert_eq!(position, Some(1));
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert!(Choices::default().check("level", "trace", None).is_ok());
+    }
+}
@@ -1,9 +1,222 @@
-use std::any::Any;
+use crate::config_file::{ConfigFile, ConfigValue};
+use std::any::{Any, type_name};
 use std::collections::HashMap;
+use std::fmt;
+
+/// Why a value could not be read from [`CmdParsingResults`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum ValueError {
+    /// No value is stored under `name`.
+    Missing { name: String },
+    /// The stored value is not a `expected`. `found` is the stored value's
+    /// type, if it was added through a typed builder.
+    WrongType {
+        name: String,
+        expected: &'static str,
+        found: Option<&'static str>,
+    },
+    /// A list of values is stored under `name`, read it with `get_values`.
+    MultipleValues { name: String },
+    /// `found` values are stored under `name` where `expected` were asked for.
+    WrongCount {
+        name: String,
+        expected: usize,
+        found: usize,
+    },
+    /// None of `actions` is on the action path.
+    MissingAction { actions: Vec<String> },
+}
+
+impl fmt::Display for ValueError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValueError::Missing { name } => write!(f, "{} not found", name),
+            ValueError::WrongType {
+                name,
+                expected,
+                found: Some(found),
+            } => write!(
+                f,
+                "value {} is not of expected type {}, it is a {}",
+                name, expected, found
+            ),
+            ValueError::WrongType {
+                name,
+                expected,
+                found: None,
+            } => write!(f, "value {} is not of expected type {}", name, expected),
+            ValueError::MultipleValues { name } => {
+                write!(f, "value {} holds multiple values", name)
+            }
+            ValueError::WrongCount {
+                name,
+                expected,
+                found,
+            } => write!(
+                f,
+                "value {} holds {} values instead of {}",
+                name, found, expected
+            ),
+            ValueError::MissingAction { actions } => {
+                write!(f, "none of the actions {} was taken", actions.join(", "))
+            }
+        }
+    }
+}
+
+impl std::error::Error for ValueError {}
+
+/// Every value that could not be read while extracting a [`FromResults`]
+/// type, so all of them can be reported at once.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExtractError {
+    errors: Vec<ValueError>,
+}
+
+impl ExtractError {
+    pub fn errors(&self) -> &[ValueError] {
+        &self.errors
+    }
+}
+
+impl fmt::Display for ExtractError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let errors: Vec<String> = self.errors.iter().map(|e| e.to_string()).collect();
+        write!(f, "could not extract results: {}", errors.join("; "))
+    }
+}
+
+impl std::error::Error for ExtractError {}
+
+impl From<ValueError> for ExtractError {
+    fn from(error: ValueError) -> ExtractError {
+        ExtractError {
+            errors: vec![error],
+        }
+    }
+}
+
+/// A type that can be built from [`CmdParsingResults`], see
+/// [`CmdParsingResults::extract`]. Implementations usually read their values
+/// through an [`Extractor`], which collects every failure on the way.
+pub trait FromResults: Sized {
+    fn try_from_results(results: &CmdParsingResults) -> Result<Self, ExtractError>;
+}
+
+/// Reads values from [`CmdParsingResults`] and remembers every failure
+/// instead of stopping at the first one. A `None` returned by one of its
+/// reads means the failure was recorded and will be reported by `finish`.
+pub struct Extractor<'a> {
+    results: &'a CmdParsingResults,
+    errors: Vec<ValueError>,
+}
+
+impl<'a> Extractor<'a> {
+    pub fn results(&self) -> &'a CmdParsingResults {
+        self.results
+    }
+
+    /// Reads the value stored under `name`, which must exist and be a `T`.
+    pub fn value<T: Clone + 'static>(&mut self, name: &str) -> Option<T> {
+        match self.results.try_get_value::<T>(name) {
+            Ok(value) => Some(value.clone()),
+            Err(e) => {
+                self.errors.push(e);
+                None
+            }
+        }
+    }
+
+    /// Reads the value stored under `name`; only a value of another type than
+    /// `T` is a failure, a missing one is `None`.
+    pub fn optional_value<T: Clone + 'static>(&mut self, name: &str) -> Option<T> {
+        match self.results.try_get_value::<T>(name) {
+            Ok(value) => Some(value.clone()),
+            Err(ValueError::Missing { .. }) => None,
+            Err(e) => {
+                self.errors.push(e);
+                None
+            }
+        }
+    }
+
+    /// Reads the list of values stored under `name`, which must exist and
+    /// consist of `T`s.
+    pub fn values<T: Clone + 'static>(&mut self, name: &str) -> Option<Vec<T>> {
+        match self.results.try_get_values::<T>(name) {
+            Ok(values) => Some(values.into_iter().cloned().collect()),
+            Err(e) => {
+                self.errors.push(e);
+                None
+            }
+        }
+    }
+
+    /// Reads the `N` values stored under `name`, which must exist and consist
+    /// of `T`s.
+    pub fn array<T: Clone + 'static, const N: usize>(&mut self, name: &str) -> Option<[T; N]> {
+        match self.results.try_get_array::<T, N>(name) {
+            Ok(values) => Some(values.map(|value| value.clone())),
+            Err(e) => {
+                self.errors.push(e);
+                None
+            }
+        }
+    }
+
+    /// Extracts a nested [`FromResults`] type, keeping all of its failures.
+    pub fn extract<T: FromResults>(&mut self) -> Option<T> {
+        match T::try_from_results(self.results) {
+            Ok(value) => Some(value),
+            Err(e) => {
+                self.errors.extend(e.errors);
+                None
+            }
+        }
+    }
+
+    /// Reports every failure recorded so far.
+    pub fn finish(self) -> Result<(), ExtractError> {
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(ExtractError {
+                errors: self.errors,
+            })
+        }
+    }
+}
+
+/// Where a stored value came from, ordered from the highest precedence to
+/// the lowest.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ValueSource {
+    /// Given on the command line.
+    CommandLine,
+    /// Read from the environment variable bound with `env`.
+    Environment,
+    /// Read from a config file.
+    ConfigFile,
+    /// Filled in from a default, or the empty list of an appending optional.
+    Default,
+}
+
+/// The value(s) stored under one name. `multiple` is set for arguments that
+/// collect a list, even if it holds a single value, `turned_off` for a flag
+/// a layer below the command line explicitly set to false or zero.
+struct ResultValue {
+    values: Vec<Box<dyn Any>>,
+    type_name: Option<&'static str>,
+    multiple: bool,
+    source: ValueSource,
+    turned_off: bool,
+}
 
 pub struct CmdParsingResults {
-    results: HashMap<String, Box<dyn Any>>,
+    results: HashMap<String, ResultValue>,
     action: Option<String>,
+    action_path: Vec<String>,
+    config_files: Vec<ConfigFile>,
 }
 
 impl CmdParsingResults {
@@ -15,56 +228,285 @@ impl CmdParsingResults {
         CmdParsingResults {
             results: HashMap::new(),
             action: None,
+            action_path: Vec::new(),
+            config_files: Vec::new(),
         }
     }
 
-    pub(crate) fn set_action(&mut self, action: String) -> () {
+    pub(crate) fn set_action(&mut self, action: String) {
         self.action = Some(action);
     }
 
+    pub(crate) fn enter_action(&mut self, action: String) {
+        self.action_path.push(action.clone());
+        self.set_action(action);
+    }
+
     pub fn get_action(&self) -> String {
         self.action.clone().expect("no main set for leaf action")
     }
 
-    pub(crate) fn add_result_value(&mut self, name: String, result: Box<dyn Any>) -> () {
-        self.results.insert(name.clone(), result);
+    /// Adds a config file consulted before the ones loaded earlier.
+    pub(crate) fn add_config_file(&mut self, file: ConfigFile) {
+        self.config_files.insert(0, file);
     }
 
-    pub fn get_value<T: 'static>(&self, name: &str) -> &T {
-        match self.results.get(name) {
-            Some(value) => match value.downcast_ref::<T>() {
-                Some(value) => value,
-                None => panic!("value {} is not of expected type", name),
+    /// The loaded config files, the one taking precedence first.
+    pub(crate) fn config_files(&self) -> &[ConfigFile] {
+        &self.config_files
+    }
+
+    /// The value for the first of `keys` found in the section at `section`
+    /// of the config file taking precedence.
+    pub(crate) fn config_value(&self, section: &[String], keys: &[&str]) -> Option<ConfigValue> {
+        self.config_files
+            .iter()
+            .find_map(|file| keys.iter().find_map(|key| file.get(section, key)).cloned())
+    }
+
+    /// The root parser's name followed by every sub-action taken so far.
+    pub fn get_action_path(&self) -> &[String] {
+        &self.action_path
+    }
+
+    #[cfg(test)]
+    pub(crate) fn add_result_value(&mut self, name: String, result: Box<dyn Any>) {
+        self.add_typed_result_value(name, result, None);
+    }
+
+    pub(crate) fn add_typed_result_value(
+        &mut self,
+        name: String,
+        result: Box<dyn Any>,
+        type_name: Option<&'static str>,
+    ) {
+        self.results.insert(
+            name,
+            ResultValue {
+                values: vec![result],
+                type_name,
+                multiple: false,
+                source: ValueSource::CommandLine,
+                turned_off: false,
             },
-            None => panic!("{} not found", name),
+        );
+    }
+
+    /// Stores a (possibly empty) list of values under `name`, replacing any
+    /// earlier value.
+    pub(crate) fn add_result_values(
+        &mut self,
+        name: String,
+        results: Vec<Box<dyn Any>>,
+        type_name: Option<&'static str>,
+    ) {
+        self.results.insert(
+            name,
+            ResultValue {
+                values: results,
+                type_name,
+                multiple: true,
+                source: ValueSource::CommandLine,
+                turned_off: false,
+            },
+        );
+    }
+
+    /// Appends a value to the list stored under `name`.
+    pub(crate) fn append_result_value(
+        &mut self,
+        name: String,
+        result: Box<dyn Any>,
+        type_name: Option<&'static str>,
+    ) {
+        let entry = self.results.entry(name).or_insert(ResultValue {
+            values: Vec::new(),
+            type_name,
+            multiple: true,
+            source: ValueSource::CommandLine,
+            turned_off: false,
+        });
+        entry.values.push(result);
+    }
+
+    /// Records where the value stored under `name` came from. Values are
+    /// stored as given on the command line until marked otherwise.
+    pub(crate) fn set_value_source(&mut self, name: &str, source: ValueSource) {
+        if let Some(entry) = self.results.get_mut(name) {
+            entry.source = source;
         }
     }
 
-    pub fn get_optional_value<T: 'static>(&self, name: &str) -> Option<&T> {
+    /// Marks the flag stored under `name` as explicitly turned off, so it is
+    /// not given even though a layer set it.
+    pub(crate) fn set_turned_off(&mut self, name: &str) {
+        if let Some(entry) = self.results.get_mut(name) {
+            entry.turned_off = true;
+        }
+    }
+
+    /// Drops the value stored under `name`, e.g. one overridden by a higher
+    /// layer, so the default is filled in instead.
+    pub(crate) fn remove_value(&mut self, name: &str) {
+        self.results.remove(name);
+    }
+
+    /// Where the value stored under `name` came from, `None` if nothing is
+    /// stored under it.
+    pub fn value_source(&self, name: &str) -> Option<ValueSource> {
+        self.results.get(name).map(|entry| entry.source)
+    }
+
+    /// Whether a value for `name` was given, on the command line, in the
+    /// environment or in a config file, rather than filled in from a default.
+    /// A flag the environment or a config file turns off is not given.
+    pub fn is_given(&self, name: &str) -> bool {
+        self.results
+            .get(name)
+            .is_some_and(|entry| entry.source != ValueSource::Default && !entry.turned_off)
+    }
+
+    /// Like [`CmdParsingResults::get_value`], but reports a missing or
+    /// mistyped value instead of panicking.
+    pub fn try_get_value<T: 'static>(&self, name: &str) -> Result<&T, ValueError> {
         match self.results.get(name) {
-            Some(value) => match value.downcast_ref::<T>() {
-                Some(value) => Some(value),
-                None => None,
+            Some(entry) if entry.multiple => Err(ValueError::MultipleValues {
+                name: name.to_string(),
+            }),
+            Some(entry) => match entry.values[0].downcast_ref::<T>() {
+                Some(value) => Ok(value),
+                None => Err(ValueError::WrongType {
+                    name: name.to_string(),
+                    expected: type_name::<T>(),
+                    found: entry.type_name,
+                }),
             },
-            None => None,
+            None => Err(ValueError::Missing {
+                name: name.to_string(),
+            }),
+        }
+    }
+
+    /// Like [`CmdParsingResults::get_values`], but reports a missing or
+    /// mistyped value instead of panicking.
+    pub fn try_get_values<T: 'static>(&self, name: &str) -> Result<Vec<&T>, ValueError> {
+        match self.results.get(name) {
+            Some(entry) => entry
+                .values
+                .iter()
+                .map(|value| {
+                    value
+                        .downcast_ref::<T>()
+                        .ok_or_else(|| ValueError::WrongType {
+                            name: name.to_string(),
+                            expected: type_name::<T>(),
+                            found: entry.type_name,
+                        })
+                })
+                .collect(),
+            None => Err(ValueError::Missing {
+                name: name.to_string(),
+            }),
+        }
+    }
+
+    /// All values stored under `name`, e.g. every occurrence of an appending
+    /// optional. A single value is returned as a list of one.
+    pub fn get_values<T: 'static>(&self, name: &str) -> Vec<&T> {
+        match self.try_get_values::<T>(name) {
+            Ok(values) => values,
+            Err(e) => panic!("{}", e),
         }
     }
 
+    /// Like [`CmdParsingResults::get_array`], but reports a missing or
+    /// mistyped value, or another number of values, instead of panicking.
+    pub fn try_get_array<T: 'static, const N: usize>(
+        &self,
+        name: &str,
+    ) -> Result<[&T; N], ValueError> {
+        let values = self.try_get_values::<T>(name)?;
+        let found = values.len();
+        values.try_into().map_err(|_| ValueError::WrongCount {
+            name: name.to_string(),
+            expected: N,
+            found,
+        })
+    }
+
+    /// The `N` values stored under `name`, e.g. of an optional taking
+    /// `Nargs::Exactly(N)` values, so `let [low, high] = ...` reads a range.
+    pub fn get_array<T: 'static, const N: usize>(&self, name: &str) -> [&T; N] {
+        match self.try_get_array::<T, N>(name) {
+            Ok(values) => values,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    pub fn get_value<T: 'static>(&self, name: &str) -> &T {
+        match self.try_get_value::<T>(name) {
+            Ok(value) => value,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    pub fn get_optional_value<T: 'static>(&self, name: &str) -> Option<&T> {
+        self.try_get_value::<T>(name).ok()
+    }
+
     pub fn get_flag(&self, name: &str) -> bool {
         match self.results.get(name) {
-            Some(value) => match value.downcast_ref::<bool>() {
+            Some(entry) => match entry.values[0].downcast_ref::<bool>() {
                 Some(value) => *value,
                 None => panic!("value {} is not a flag", name),
             },
             None => panic!("flag {} not found", name),
         }
     }
+
+    pub fn extractor(&self) -> Extractor<'_> {
+        Extractor {
+            results: self,
+            errors: Vec::new(),
+        }
+    }
+
+    /// Builds a `T` from the results, listing every missing or mistyped value
+    /// if that fails.
+    pub fn extract<T: FromResults>(&self) -> Result<T, ExtractError> {
+        T::try_from_results(self)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[derive(Debug, PartialEq)]
+    struct Opts {
+        name: String,
+        count: i32,
+        verbose: bool,
+        level: Option<String>,
+    }
+
+    impl FromResults for Opts {
+        fn try_from_results(results: &CmdParsingResults) -> Result<Self, ExtractError> {
+            let mut extractor = results.extractor();
+            let name = extractor.value::<String>("name");
+            let count = extractor.value::<i32>("count");
+            let verbose = extractor.value::<bool>("verbose");
+            let level = extractor.optional_value::<String>("level");
+            extractor.finish()?;
+            Ok(Opts {
+                name: name.expect("checked by finish"),
+                count: count.expect("checked by finish"),
+                verbose: verbose.expect("checked by finish"),
+                level,
+            })
+        }
+    }
+
     #[test]
     fn set_and_get_action() {
         let mut res: CmdParsingResults = CmdParsingResults::new();
@@ -80,6 +522,18 @@ mod tests {
         assert_eq!(res.get_action(), "second");
     }
 
+    #[test]
+    fn enter_action_extends_action_path() {
+        let mut res: CmdParsingResults = CmdParsingResults::new();
+        res.enter_action("tool".to_string());
+        res.enter_action("start".to_string());
+        assert_eq!(res.get_action(), "start");
+        assert_eq!(
+            res.get_action_path(),
+            ["tool".to_string(), "start".to_string()]
+        );
+    }
+
     #[test]
     #[should_panic(expected = "no main set for leaf action")]
     fn get_action_without_set_panics() {
@@ -109,6 +563,130 @@ mod tests {
         res.get_value::<i32>("test");
     }
 
+    #[test]
+    fn try_get_value_reports_recorded_type() {
+        let mut res: CmdParsingResults = CmdParsingResults::new();
+        res.add_typed_result_value(
+            "test".to_string(),
+            Box::new(String::from("hello")),
+            Some(type_name::<String>()),
+        );
+        assert_eq!(
+            res.try_get_value::<i32>("test").unwrap_err(),
+            ValueError::WrongType {
+                name: "test".to_string(),
+                expected: "i32",
+                found: Some("alloc::string::String"),
+            }
+        );
+    }
+
+    #[test]
+    fn try_get_value_reports_missing_value() {
+        let res: CmdParsingResults = CmdParsingResults::new();
+        assert_eq!(
+            res.try_get_value::<i32>("test").unwrap_err().to_string(),
+            "test not found"
+        );
+    }
+
+    #[test]
+    fn extract_builds_struct() {
+        let mut res: CmdParsingResults = CmdParsingResults::new();
+        res.add_result_value("name".to_string(), Box::new(String::from("test")));
+        res.add_result_value("count".to_string(), Box::new(3_i32));
+        res.add_result_value("verbose".to_string(), Box::new(true));
+        assert_eq!(
+            res.extract::<Opts>(),
+            Ok(Opts {
+                name: "test".to_string(),
+                count: 3,
+                verbose: true,
+                level: None,
+            })
+        );
+    }
+
+    #[test]
+    fn extract_lists_every_missing_and_mistyped_value() {
+        let mut res: CmdParsingResults = CmdParsingResults::new();
+        res.add_typed_result_value(
+            "count".to_string(),
+            Box::new(String::from("three")),
+            Some(type_name::<String>()),
+        );
+        res.add_result_value("verbose".to_string(), Box::new(true));
+        res.add_result_value("level".to_string(), Box::new(1_u8));
+        let error = res.extract::<Opts>().unwrap_err();
+        assert_eq!(
+            error.errors(),
+            [
+                ValueError::Missing {
+                    name: "name".to_string()
+                },
+                ValueError::WrongType {
+                    name: "count".to_string(),
+                    expected: "i32",
+                    found: Some("alloc::string::String"),
+                },
+                ValueError::WrongType {
+                    name: "level".to_string(),
+                    expected: "alloc::string::String",
+                    found: None,
+                },
+            ]
+        );
+        assert_eq!(
+            error.to_string(),
+            "could not extract results: name not found; \
+             value count is not of expected type i32, it is a alloc::string::String; \
+             value level is not of expected type alloc::string::String"
+        );
+    }
+
+    #[test]
+    fn append_and_retrieve_values() {
+        let mut res: CmdParsingResults = CmdParsingResults::new();
+        res.append_result_value("include".to_string(), Box::new(String::from("a")), None);
+        res.append_result_value("include".to_string(), Box::new(String::from("b")), None);
+        assert_eq!(res.get_values::<String>("include"), ["a", "b"]);
+        assert_eq!(
+            res.try_get_value::<String>("include").unwrap_err(),
+            ValueError::MultipleValues {
+                name: "include".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn retrieve_single_value_as_values() {
+        let mut res: CmdParsingResults = CmdParsingResults::new();
+        res.add_result_value("count".to_string(), Box::new(42_i32));
+        assert_eq!(res.get_values::<i32>("count"), [&42]);
+    }
+
+    #[test]
+    fn retrieve_empty_values() {
+        let mut res: CmdParsingResults = CmdParsingResults::new();
+        res.add_result_values("include".to_string(), Vec::new(), None);
+        assert!(res.get_values::<String>("include").is_empty());
+    }
+
+    #[test]
+    fn try_get_array_checks_the_count() {
+        let mut res: CmdParsingResults = CmdParsingResults::new();
+        res.add_result_values("range".to_string(), vec![Box::new(1), Box::new(10)], None);
+        assert_eq!(res.get_array::<i32, 2>("range"), [&1, &10]);
+        assert_eq!(
+            res.try_get_array::<i32, 3>("range").unwrap_err(),
+            ValueError::WrongCount {
+                name: "range".to_string(),
+                expected: 3,
+                found: 2
+            }
+        );
+    }
+
     #[test]
     fn add_and_retrieve_optional_existing_value() {
         let mut res: CmdParsingResults = CmdParsingResults::new();
@@ -136,7 +714,7 @@ mod tests {
     fn retrieve_flag_value_that_exists() {
         let mut res: CmdParsingResults = CmdParsingResults::new();
         res.add_result_value("test".to_string(), Box::new(true));
-        assert_eq!(res.get_flag("test"), true);
+        assert!(res.get_flag("test"));
     }
 
     #[test]
@@ -0,0 +1,597 @@
+//! Tab completion: static scripts generated by
+//! [`Parser::generate_completion`](crate::Parser::generate_completion), and
+//! the dynamic protocol behind [`Parser::complete`](crate::Parser::complete).
+
+use crate::Completer;
+use crate::choices::Choices;
+use std::fmt::Write as _;
+use std::io;
+use std::str::FromStr;
+
+/// The environment variable switching
+/// [`Parser::parse_cmdline`](crate::Parser::parse_cmdline) to completion.
+pub(crate) const COMPLETE_VAR: &str = "COMPLETE";
+
+/// A shell [`Parser::generate_completion`](crate::Parser::generate_completion)
+/// writes a completion script for.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+impl FromStr for Shell {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Shell, String> {
+        match name {
+            "bash" => Ok(Shell::Bash),
+            "zsh" => Ok(Shell::Zsh),
+            "fish" => Ok(Shell::Fish),
+            _ => Err(format!(
+                "unsupported shell '{}', expected bash, zsh or fish",
+                name
+            )),
+        }
+    }
+}
+
+/// What a completion script offers at one level of the parser tree.
+pub(crate) struct Command {
+    pub(crate) names: Vec<String>,
+    pub(crate) doc: String,
+    pub(crate) switches: Vec<Switch>,
+    /// The choices of the positionals, offered as words.
+    pub(crate) values: Vec<Candidate>,
+    pub(crate) actions: Vec<Command>,
+}
+
+/// An optional (`takes_value`) or flag, with its short and long spelling.
+pub(crate) struct Switch {
+    pub(crate) short: char,
+    pub(crate) long: String,
+    pub(crate) doc: String,
+    pub(crate) takes_value: bool,
+    pub(crate) choices: Vec<String>,
+}
+
+pub(crate) struct Candidate {
+    pub(crate) word: String,
+    pub(crate) doc: String,
+}
+
+impl Command {
+    fn name(&self) -> &str {
+        &self.names[0]
+    }
+
+    /// Every level of the tree with its path, the names of the actions
+    /// leading to it joined by spaces, starting with this one.
+    fn levels(&self) -> Vec<(String, &Command)> {
+        let mut levels = vec![(self.name().to_string(), self)];
+        let mut next = 0;
+        while next < levels.len() {
+            let (path, command) = levels[next].clone();
+            for action in &command.actions {
+                levels.push((format!("{} {}", path, action.name()), action));
+            }
+            next += 1;
+        }
+        levels
+    }
+
+    /// The `path:token` patterns entering each sub-action, with the path of
+    /// that sub-action, for tracking the level in the typed command line.
+    fn transitions(&self) -> Vec<(Vec<String>, String)> {
+        let mut transitions = Vec::new();
+        for (path, command) in self.levels() {
+            for action in &command.actions {
+                let patterns = action
+                    .names
+                    .iter()
+                    .map(|name| format!("{}:{}", path, name))
+                    .collect();
+                transitions.push((patterns, format!("{} {}", path, action.name())));
+            }
+        }
+        transitions
+    }
+
+    /// The `path:switch` patterns of the switches taking a value, at every
+    /// level, whose value is skipped when tracking the level.
+    fn value_switch_patterns(&self) -> Vec<String> {
+        let mut patterns = Vec::new();
+        for (path, command) in self.levels() {
+            for switch in command.value_switches() {
+                patterns.push(format!("{}:--{}", path, switch.long));
+                patterns.push(format!("{}:-{}", path, switch.short));
+            }
+        }
+        patterns
+    }
+
+    /// The words offered for a new argument at this level, each with its doc.
+    fn candidates(&self) -> Vec<Candidate> {
+        let mut candidates = Vec::new();
+        for action in &self.actions {
+            for name in &action.names {
+                candidates.push(Candidate {
+                    word: name.clone(),
+                    doc: action.doc.clone(),
+                });
+            }
+        }
+        for switch in &self.switches {
+            candidates.push(Candidate {
+                word: format!("--{}", switch.long),
+                doc: switch.doc.clone(),
+            });
+            candidates.push(Candidate {
+                word: format!("-{}", switch.short),
+                doc: switch.doc.clone(),
+            });
+        }
+        for value in &self.values {
+            candidates.push(Candidate {
+                word: value.word.clone(),
+                doc: value.doc.clone(),
+            });
+        }
+        candidates
+    }
+
+    /// The switches taking a value, which is completed instead of a new
+    /// argument right after them.
+    fn value_switches(&self) -> impl Iterator<Item = &Switch> {
+        self.switches.iter().filter(|switch| switch.takes_value)
+    }
+}
+
+/// The name of the shell functions generated for the program `name`, which
+/// only allow letters, digits and underscores.
+fn function_name(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+/// Quotes `text` for bash and zsh.
+fn quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "'\\''"))
+}
+
+/// The bash loop replying with those of `words` that start with the current
+/// word. Unlike `compgen -W`, it keeps words holding spaces whole.
+fn bash_reply(words: &[String]) -> String {
+    let words: Vec<String> = words.iter().map(|word| quote(word)).collect();
+    format!(
+        "for word in {}; do [[ $word == \"$cur\"* ]] && COMPREPLY+=(\"$word\"); done",
+        words.join(" ")
+    )
+}
+
+/// Quotes `text` for fish, whose single quotes allow escapes.
+fn fish_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+/// Writes the `case` branches setting `level` from the typed words, skipping
+/// the values of switches, shared by the bash and zsh scripts.
+fn write_path_tracking(script: &mut String, command: &Command, indent: &str) {
+    let _ = writeln!(script, "{}if [ -n \"$skip\" ]; then", indent);
+    let _ = writeln!(script, "{}    skip=''", indent);
+    let _ = writeln!(script, "{}    continue", indent);
+    let _ = writeln!(script, "{}fi", indent);
+    let _ = writeln!(script, "{}case \"$level:$word\" in", indent);
+    for (patterns, path) in command.transitions() {
+        let patterns: Vec<String> = patterns.iter().map(|p| quote(p)).collect();
+        let _ = writeln!(
+            script,
+            "{}    {}) level={} ;;",
+            indent,
+            patterns.join("|"),
+            quote(&path)
+        );
+    }
+    let patterns: Vec<String> = command
+        .value_switch_patterns()
+        .iter()
+        .map(|p| quote(p))
+        .collect();
+    if !patterns.is_empty() {
+        let _ = writeln!(script, "{}    {}) skip=1 ;;", indent, patterns.join("|"));
+    }
+    let _ = writeln!(script, "{}esac", indent);
+}
+
+pub(crate) fn bash(command: &Command) -> String {
+    let function = function_name(command.name());
+    let mut script = String::new();
+    let _ = writeln!(script, "_{}() {{", function);
+    let _ = writeln!(script, "    local cur=\"${{COMP_WORDS[COMP_CWORD]}}\"");
+    let _ = writeln!(script, "    local prev=\"${{COMP_WORDS[COMP_CWORD-1]}}\"");
+    let _ = writeln!(
+        script,
+        "    local level={} word i skip=''",
+        quote(command.name())
+    );
+    let _ = writeln!(script, "    for ((i = 1; i < COMP_CWORD; i++)); do");
+    let _ = writeln!(script, "        word=\"${{COMP_WORDS[i]}}\"");
+    write_path_tracking(&mut script, command, "        ");
+    let _ = writeln!(script, "    done");
+    let _ = writeln!(script, "    COMPREPLY=()");
+    let _ = writeln!(script, "    case \"$level\" in");
+    for (path, level) in command.levels() {
+        let _ = writeln!(script, "        {})", quote(&path));
+        let switches: Vec<&Switch> = level.value_switches().collect();
+        if !switches.is_empty() {
+            let _ = writeln!(script, "            case \"$prev\" in");
+            for switch in switches {
+                let reply = if switch.choices.is_empty() {
+                    "COMPREPLY=($(compgen -f -- \"$cur\"))".to_string()
+                } else {
+                    bash_reply(&switch.choices)
+                };
+                let _ = writeln!(
+                    script,
+                    "                {}|{}) {}; return ;;",
+                    quote(&format!("--{}", switch.long)),
+                    quote(&format!("-{}", switch.short)),
+                    reply
+                );
+            }
+            let _ = writeln!(script, "            esac");
+        }
+        let words: Vec<String> = level.candidates().into_iter().map(|c| c.word).collect();
+        let _ = writeln!(script, "            {} ;;", bash_reply(&words));
+    }
+    let _ = writeln!(script, "    esac");
+    let _ = writeln!(script, "}}");
+    let _ = writeln!(
+        script,
+        "complete -o default -F _{} {}",
+        function,
+        command.name()
+    );
+    script
+}
+
+pub(crate) fn zsh(command: &Command) -> String {
+    let function = function_name(command.name());
+    let mut script = String::new();
+    let _ = writeln!(script, "#compdef {}", command.name());
+    let _ = writeln!(script);
+    let _ = writeln!(script, "_{}() {{", function);
+    let _ = writeln!(script, "    local prev=\"${{words[CURRENT-1]}}\"");
+    let _ = writeln!(
+        script,
+        "    local level={} word i skip=''",
+        quote(command.name())
+    );
+    let _ = writeln!(script, "    local -a candidates");
+    let _ = writeln!(script, "    for ((i = 2; i < CURRENT; i++)); do");
+    let _ = writeln!(script, "        word=\"${{words[i]}}\"");
+    write_path_tracking(&mut script, command, "        ");
+    let _ = writeln!(script, "    done");
+    let _ = writeln!(script, "    case \"$level\" in");
+    for (path, level) in command.levels() {
+        let _ = writeln!(script, "        {})", quote(&path));
+        let switches: Vec<&Switch> = level.value_switches().collect();
+        if !switches.is_empty() {
+            let _ = writeln!(script, "            case \"$prev\" in");
+            for switch in switches {
+                let reply = if switch.choices.is_empty() {
+                    "_files".to_string()
+                } else {
+                    let choices: Vec<String> = switch.choices.iter().map(|c| quote(c)).collect();
+                    format!("compadd -- {}", choices.join(" "))
+                };
+                let _ = writeln!(
+                    script,
+                    "                {}|{}) {}; return ;;",
+                    quote(&format!("--{}", switch.long)),
+                    quote(&format!("-{}", switch.short)),
+                    reply
+                );
+            }
+            let _ = writeln!(script, "            esac");
+        }
+        let _ = writeln!(script, "            candidates=(");
+        for candidate in level.candidates() {
+            let entry = format!("{}:{}", candidate.word.replace(':', "\\:"), candidate.doc);
+            let _ = writeln!(script, "                {}", quote(&entry));
+        }
+        let _ = writeln!(script, "            )");
+        let _ = writeln!(
+            script,
+            "            _describe {} candidates ;;",
+            quote(command.name())
+        );
+    }
+    let _ = writeln!(script, "    esac");
+    let _ = writeln!(script, "}}");
+    let _ = writeln!(script);
+    let _ = writeln!(script, "if [ \"$funcstack[1]\" = \"_{}\" ]; then", function);
+    let _ = writeln!(script, "    _{} \"$@\"", function);
+    let _ = writeln!(script, "else");
+    let _ = writeln!(script, "    compdef _{} {}", function, command.name());
+    let _ = writeln!(script, "fi");
+    script
+}
+
+pub(crate) fn fish(command: &Command) -> String {
+    let function = format!("__{}_path", function_name(command.name()));
+    let name = command.name();
+    let mut script = String::new();
+    let _ = writeln!(script, "function {}", function);
+    let _ = writeln!(script, "    set -l level {}", fish_quote(name));
+    let _ = writeln!(script, "    set -l skip 0");
+    let _ = writeln!(script, "    for word in (commandline -opc)[2..-1]");
+    let _ = writeln!(script, "        if test $skip = 1");
+    let _ = writeln!(script, "            set skip 0");
+    let _ = writeln!(script, "            continue");
+    let _ = writeln!(script, "        end");
+    let _ = writeln!(script, "        switch \"$level:$word\"");
+    for (patterns, path) in command.transitions() {
+        let patterns: Vec<String> = patterns.iter().map(|p| fish_quote(p)).collect();
+        let _ = writeln!(script, "            case {}", patterns.join(" "));
+        let _ = writeln!(script, "                set level {}", fish_quote(&path));
+    }
+    let patterns: Vec<String> = command
+        .value_switch_patterns()
+        .iter()
+        .map(|p| fish_quote(p))
+        .collect();
+    if !patterns.is_empty() {
+        let _ = writeln!(script, "            case {}", patterns.join(" "));
+        let _ = writeln!(script, "                set skip 1");
+    }
+    let _ = writeln!(script, "        end");
+    let _ = writeln!(script, "    end");
+    let _ = writeln!(script, "    test \"$level\" = \"$argv[1]\"");
+    let _ = writeln!(script, "end");
+    for (path, level) in command.levels() {
+        let _ = writeln!(script);
+        let condition = fish_quote(&format!("{} {}", function, fish_quote(&path)));
+        let prefix = format!("complete -c {} -n {}", name, condition);
+        for action in &level.actions {
+            for action_name in &action.names {
+                let _ = writeln!(
+                    script,
+                    "{} -f -a {} -d {}",
+                    prefix,
+                    fish_quote(action_name),
+                    fish_quote(&action.doc)
+                );
+            }
+        }
+        for switch in &level.switches {
+            let mut line = format!("{} -s {} -l {}", prefix, switch.short, switch.long);
+            if !switch.choices.is_empty() {
+                let choices: Vec<String> = switch.choices.iter().map(|c| fish_quote(c)).collect();
+                line += &format!(" -x -a {}", fish_quote(&choices.join(" ")));
+            } else if switch.takes_value {
+                line += " -r";
+            }
+            let _ = writeln!(script, "{} -d {}", line, fish_quote(&switch.doc));
+        }
+        for value in &level.values {
+            let _ = writeln!(
+                script,
+                "{} -a {} -d {}",
+                prefix,
+                fish_quote(&value.word),
+                fish_quote(&value.doc)
+            );
+        }
+    }
+    script
+}
+
+/// The candidates for a value: its choices, then whatever its completer lists
+/// that the choices allow, as far as they start with `partial`. Each value is
+/// offered once.
+pub(crate) fn value_candidates(
+    choices: &Choices,
+    completer: Option<Completer>,
+    partial: &str,
+) -> Vec<Candidate> {
+    let completed = completer.map_or(Vec::new(), |completer| completer(partial));
+    let allowed = |value: &String| !choices.is_restricted() || choices.values().contains(value);
+    let mut values: Vec<String> = Vec::new();
+    for value in choices.values().iter().cloned().chain(completed) {
+        if value.starts_with(partial) && allowed(&value) && !values.contains(&value) {
+            values.push(value);
+        }
+    }
+    values
+        .into_iter()
+        .map(|word| Candidate {
+            word,
+            doc: String::new(),
+        })
+        .collect()
+}
+
+/// Writes one candidate per line, followed by a tab and its doc if it has
+/// one. The format is the same for every shell.
+pub(crate) fn write_candidates(
+    candidates: &[Candidate],
+    out: &mut impl io::Write,
+) -> io::Result<()> {
+    for candidate in candidates {
+        if candidate.doc.is_empty() {
+            writeln!(out, "{}", candidate.word)?;
+        } else {
+            writeln!(out, "{}\t{}", candidate.word, candidate.doc)?;
+        }
+    }
+    Ok(())
+}
+
+/// The script making `shell` complete `name` by running it again with
+/// `COMPLETE` set and the typed words after `--`.
+pub(crate) fn register(shell: Shell, name: &str) -> String {
+    let function = format!("_{}_complete", function_name(name));
+    let mut script = String::new();
+    match shell {
+        Shell::Bash => {
+            let _ = writeln!(script, "{}() {{", function);
+            let _ = writeln!(script, "    local IFS=$'\\n'");
+            let _ = writeln!(
+                script,
+                "    COMPREPLY=($({}=bash \"${{COMP_WORDS[0]}}\" -- \"${{COMP_WORDS[@]:1:COMP_CWORD}}\" | cut -f1))",
+                COMPLETE_VAR
+            );
+            let _ = writeln!(script, "}}");
+            let _ = writeln!(script, "complete -o default -F {} {}", function, name);
+        }
+        Shell::Zsh => {
+            let _ = writeln!(script, "#compdef {}", name);
+            let _ = writeln!(script);
+            let _ = writeln!(script, "{}() {{", function);
+            let _ = writeln!(script, "    local -a candidates");
+            let _ = writeln!(
+                script,
+                "    candidates=(${{(f)\"$({}=zsh \"${{words[1]}}\" -- \"${{(@)words[2,CURRENT]}}\")\"}})",
+                COMPLETE_VAR
+            );
+            let _ = writeln!(script, "    candidates=(\"${{(@)candidates//:/\\:}}\")");
+            let _ = writeln!(script, "    candidates=(\"${{(@)candidates/$'\\t'/:}}\")");
+            let _ = writeln!(script, "    if (( ${{#candidates}} )); then");
+            let _ = writeln!(script, "        _describe {} candidates", quote(name));
+            let _ = writeln!(script, "    else");
+            let _ = writeln!(script, "        _files");
+            let _ = writeln!(script, "    fi");
+            let _ = writeln!(script, "}}");
+            let _ = writeln!(script, "compdef {} {}", function, name);
+        }
+        Shell::Fish => {
+            let _ = writeln!(
+                script,
+                "complete -c {} -f -a '({}=fish (commandline -opc)[1] -- (commandline -opc)[2..-1] (commandline -ct))'",
+                name, COMPLETE_VAR
+            );
+        }
+    }
+    script
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn command() -> Command {
+        Command {
+            names: vec!["tool".to_string()],
+            doc: "a tool".to_string(),
+            switches: vec![Switch {
+                short: 'v',
+                long: "verbose".to_string(),
+                doc: "say more".to_string(),
+                takes_value: false,
+                choices: Vec::new(),
+            }],
+            values: Vec::new(),
+            actions: vec![Command {
+                names: vec!["serve".to_string(), "s".to_string()],
+                doc: "serve it's files".to_string(),
+                switches: vec![Switch {
+                    short: 'l',
+                    long: "level".to_string(),
+                    doc: "log level".to_string(),
+                    takes_value: true,
+                    choices: vec!["debug".to_string(), "very verbose".to_string()],
+                }],
+                values: Vec::new(),
+                actions: Vec::new(),
+            }],
+        }
+    }
+
+    #[test]
+    fn shell_from_str() {
+        assert_eq!("zsh".parse::<Shell>(), Ok(Shell::Zsh));
+        assert!("tcsh".parse::<Shell>().is_err());
+    }
+
+    #[test]
+    fn bash_tracks_actions_by_name_and_alias() {
+        let script = bash(&command());
+        assert!(script.contains("'tool:serve'|'tool:s') level='tool serve' ;;"));
+        assert!(script.contains("'tool serve:--level'|'tool serve:-l') skip=1 ;;"));
+        assert!(script.contains(
+            "'--level'|'-l') for word in 'debug' 'very verbose'; do [[ $word == \"$cur\"* ]] && COMPREPLY+=(\"$word\"); done; return ;;"
+        ));
+        assert!(script.contains("for word in 'serve' 's' '--verbose' '-v'; do"));
+        assert!(script.ends_with("complete -o default -F _tool tool\n"));
+    }
+
+    #[test]
+    fn zsh_and_fish_describe_candidates() {
+        let script = zsh(&command());
+        assert!(script.starts_with("#compdef tool\n"));
+        assert!(script.contains("'serve:serve it'\\''s files'"));
+        assert!(script.contains("'--level'|'-l') compadd -- 'debug' 'very verbose'; return ;;"));
+        assert!(script.contains("'tool serve:--level'|'tool serve:-l') skip=1 ;;"));
+        let script = fish(&command());
+        assert!(script.contains(
+            "complete -c tool -n '__tool_path \\'tool serve\\'' -s l -l level -x -a '\\'debug\\' \\'very verbose\\'' -d 'log level'"
+        ));
+        assert!(script.contains(
+            "            case 'tool serve:--level' 'tool serve:-l'\n                set skip 1\n"
+        ));
+        assert!(script.contains("-f -a 's' -d 'serve it\\'s files'"));
+    }
+
+    #[test]
+    fn value_candidates_are_unique_and_allowed() {
+        fn completer(_partial: &str) -> Vec<String> {
+            vec![
+                "web".to_string(),
+                "api".to_string(),
+                "web".to_string(),
+                "db".to_string(),
+            ]
+        }
+        let words = |candidates: Vec<Candidate>| -> Vec<String> {
+            candidates.into_iter().map(|c| c.word).collect()
+        };
+        let restricted = Choices::new(&["api", "web"]);
+        assert_eq!(
+            words(value_candidates(&restricted, Some(completer), "")),
+            ["api", "web"]
+        );
+        assert_eq!(
+            words(value_candidates(&Choices::default(), Some(completer), "")),
+            ["web", "api", "db"]
+        );
+        assert_eq!(
+            words(value_candidates(&Choices::default(), Some(completer), "w")),
+            ["web"]
+        );
+    }
+
+    #[test]
+    fn dynamic_protocol_is_shell_agnostic() {
+        let candidates = [
+            Candidate {
+                word: "serve".to_string(),
+                doc: "serve files".to_string(),
+            },
+            Candidate {
+                word: "api".to_string(),
+                doc: String::new(),
+            },
+        ];
+        let mut out = Vec::new();
+        write_candidates(&candidates, &mut out).unwrap();
+        assert_eq!(out, b"serve\tserve files\napi\n");
+        assert!(
+            register(Shell::Bash, "my-tool")
+                .contains("complete -o default -F _my_tool_complete my-tool")
+        );
+        assert!(register(Shell::Fish, "tool").contains("COMPLETE=fish (commandline -opc)[1] --"));
+    }
+}
@@ -0,0 +1,525 @@
+use crate::parse_error::ParseError;
+use std::fs;
+use std::path::Path;
+
+/// A value read from a config file: a single token, or an array holding one
+/// token per element.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum ConfigValue {
+    Scalar(String),
+    List(Vec<String>),
+}
+
+/// The entries of one `[section]`, whose path names the sub-actions leading
+/// to it; the entries before any header have an empty path.
+struct ConfigSection {
+    path: Vec<String>,
+    entries: Vec<ConfigEntry>,
+}
+
+/// A `key = value` pair and the line it starts on, counting from one.
+struct ConfigEntry {
+    key: String,
+    value: ConfigValue,
+    line: usize,
+}
+
+/// The key/value pairs of a config file, either a subset of TOML and INI or,
+/// for a `.json` file, a JSON object whose nested objects are the sections.
+pub(crate) struct ConfigFile {
+    path: String,
+    sections: Vec<ConfigSection>,
+}
+
+impl ConfigFile {
+    pub(crate) fn load(path: &str) -> Result<ConfigFile, ParseError> {
+        let text = fs::read_to_string(path).map_err(|e| failure(path, None, e.to_string()))?;
+        ConfigFile::parse(path, &text)
+    }
+
+    /// Reads `text`, as JSON if `path` ends in `.json`.
+    pub(crate) fn parse(path: &str, text: &str) -> Result<ConfigFile, ParseError> {
+        let is_json = Path::new(path)
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("json"));
+        let sections = if is_json {
+            JsonReader::new(text).read_document()
+        } else {
+            read_ini(text)
+        }
+        .map_err(|(line, reason)| failure(path, Some(line), reason))?;
+        Ok(ConfigFile {
+            path: path.to_string(),
+            sections,
+        })
+    }
+
+    pub(crate) fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// The last value given for `key` in the section at `section`.
+    pub(crate) fn get(&self, section: &[String], key: &str) -> Option<&ConfigValue> {
+        self.sections
+            .iter()
+            .filter(|current| current.path == section)
+            .flat_map(|current| current.entries.iter())
+            .rfind(|entry| entry.key == key)
+            .map(|entry| &entry.value)
+    }
+
+    /// The keys given in the section at `section`, with their lines.
+    pub(crate) fn keys<'a>(
+        &'a self,
+        section: &'a [String],
+    ) -> impl Iterator<Item = (&'a str, usize)> {
+        self.sections
+            .iter()
+            .filter(move |current| current.path == section)
+            .flat_map(|current| {
+                current
+                    .entries
+                    .iter()
+                    .map(|entry| (entry.key.as_str(), entry.line))
+            })
+    }
+
+    /// The names of the sections directly below the one at `section`.
+    pub(crate) fn subsections<'a>(
+        &'a self,
+        section: &'a [String],
+    ) -> impl Iterator<Item = &'a str> {
+        self.sections
+            .iter()
+            .filter_map(move |current| match current.path.strip_prefix(section) {
+                Some([next, ..]) => Some(next.as_str()),
+                _ => None,
+            })
+    }
+}
+
+/// A [`ParseError::ConfigFailed`] for the file at `path`.
+pub(crate) fn failure(path: &str, line: Option<usize>, reason: String) -> ParseError {
+    ParseError::ConfigFailed {
+        path: path.to_string(),
+        line,
+        reason,
+        context: Box::default(),
+    }
+}
+
+/// Reads `key = value` (or `key: value`) lines under `[section.sub]`
+/// headers. `#` and `;` start comments, values may be quoted and `[a, b]` is
+/// an array. Failures carry the line number, counting from one.
+fn read_ini(text: &str) -> Result<Vec<ConfigSection>, (usize, String)> {
+    let mut sections = vec![ConfigSection {
+        path: Vec::new(),
+        entries: Vec::new(),
+    }];
+    for (index, raw) in text.lines().enumerate() {
+        let line = raw.trim();
+        let number = index + 1;
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        if let Some(header) = line.strip_prefix('[') {
+            let Some((name, rest)) = header.split_once(']') else {
+                return Err((number, "unterminated section header".to_string()));
+            };
+            if !is_blank_or_comment(rest) {
+                return Err((number, format!("unexpected '{}' after header", rest.trim())));
+            }
+            let path: Vec<String> = name
+                .split('.')
+                .map(|part| part.trim().to_string())
+                .collect();
+            if path.iter().any(|part| part.is_empty()) {
+                return Err((number, format!("invalid section name '{}'", name)));
+            }
+            sections.push(ConfigSection {
+                path,
+                entries: Vec::new(),
+            });
+            continue;
+        }
+        let Some(split) = line.find(['=', ':']) else {
+            return Err((number, format!("expected 'key = value', got '{}'", line)));
+        };
+        let key = unquote_key(line[..split].trim());
+        if key.is_empty() {
+            return Err((number, "missing key before '='".to_string()));
+        }
+        let value = read_ini_value(line[split + 1..].trim()).map_err(|reason| (number, reason))?;
+        sections
+            .last_mut()
+            .expect("there is always a section")
+            .entries
+            .push(ConfigEntry {
+                key,
+                value,
+                line: number,
+            });
+    }
+    Ok(sections)
+}
+
+fn unquote_key(key: &str) -> String {
+    key.strip_prefix('"')
+        .and_then(|key| key.strip_suffix('"'))
+        .unwrap_or(key)
+        .to_string()
+}
+
+fn is_blank_or_comment(rest: &str) -> bool {
+    let rest = rest.trim();
+    rest.is_empty() || rest.starts_with('#') || rest.starts_with(';')
+}
+
+fn read_ini_value(text: &str) -> Result<ConfigValue, String> {
+    if let Some(items) = text.strip_prefix('[') {
+        let mut values = Vec::new();
+        let mut rest = items.trim_start();
+        loop {
+            if let Some(after) = rest.strip_prefix(']') {
+                if !is_blank_or_comment(after) {
+                    return Err(format!("unexpected '{}' after array", after.trim()));
+                }
+                return Ok(ConfigValue::List(values));
+            }
+            let (value, after) = read_ini_scalar(rest, &[',', ']'])?;
+            values.push(value);
+            rest = after.trim_start();
+            if let Some(after) = rest.strip_prefix(',') {
+                rest = after.trim_start();
+            } else if !rest.starts_with(']') {
+                return Err("unterminated array".to_string());
+            }
+        }
+    }
+    let (value, rest) = read_ini_scalar(text, &[])?;
+    if !is_blank_or_comment(rest) {
+        return Err(format!("unexpected '{}' after value", rest.trim()));
+    }
+    Ok(ConfigValue::Scalar(value))
+}
+
+/// Reads a quoted string, or a bare value up to one of `ends`, a comment or
+/// the end of the line. Returns the value and what follows it.
+fn read_ini_scalar<'a>(text: &'a str, ends: &[char]) -> Result<(String, &'a str), String> {
+    if let Some(literal) = text.strip_prefix('\'') {
+        return match literal.split_once('\'') {
+            Some((value, rest)) => Ok((value.to_string(), rest)),
+            None => Err("unterminated string".to_string()),
+        };
+    }
+    if let Some(quoted) = text.strip_prefix('"') {
+        let mut value = String::new();
+        let mut chars = quoted.char_indices();
+        while let Some((index, c)) = chars.next() {
+            match c {
+                '"' => return Ok((value, &quoted[index + 1..])),
+                '\\' => match chars.next() {
+                    Some((_, 'n')) => value.push('\n'),
+                    Some((_, 't')) => value.push('\t'),
+                    Some((_, escaped @ ('"' | '\\'))) => value.push(escaped),
+                    Some((_, other)) => return Err(format!("unknown escape '\\{}'", other)),
+                    None => break,
+                },
+                _ => value.push(c),
+            }
+        }
+        return Err("unterminated string".to_string());
+    }
+    let end = text
+        .char_indices()
+        .find(|(index, c)| {
+            ends.contains(c)
+                || ((*c == '#' || *c == ';')
+                    && (*index == 0 || text[..*index].ends_with(char::is_whitespace)))
+        })
+        .map_or(text.len(), |(index, _)| index);
+    Ok((text[..end].trim().to_string(), &text[end..]))
+}
+
+/// A reader for a JSON object whose members are values or, as sections,
+/// nested objects.
+struct JsonReader<'a> {
+    text: &'a str,
+    offset: usize,
+}
+
+impl<'a> JsonReader<'a> {
+    fn new(text: &'a str) -> JsonReader<'a> {
+        JsonReader { text, offset: 0 }
+    }
+
+    fn read_document(mut self) -> Result<Vec<ConfigSection>, (usize, String)> {
+        let mut sections = Vec::new();
+        self.read_object(Vec::new(), &mut sections)?;
+        self.skip_whitespace();
+        if self.offset != self.text.len() {
+            return Err(self.error("unexpected text after the object"));
+        }
+        Ok(sections)
+    }
+
+    fn read_object(
+        &mut self,
+        path: Vec<String>,
+        sections: &mut Vec<ConfigSection>,
+    ) -> Result<(), (usize, String)> {
+        self.expect('{')?;
+        let index = sections.len();
+        sections.push(ConfigSection {
+            path: path.clone(),
+            entries: Vec::new(),
+        });
+        if self.consume('}') {
+            return Ok(());
+        }
+        loop {
+            self.skip_whitespace();
+            let line = self.line();
+            let key = self.read_string()?;
+            self.expect(':')?;
+            self.skip_whitespace();
+            if self.peek() == Some('{') {
+                let mut nested = path.clone();
+                nested.push(key);
+                self.read_object(nested, sections)?;
+            } else if let Some(value) = self.read_value()? {
+                sections[index]
+                    .entries
+                    .push(ConfigEntry { key, value, line });
+            }
+            if self.consume('}') {
+                return Ok(());
+            }
+            self.expect(',')?;
+        }
+    }
+
+    /// A value other than an object, `None` for `null`.
+    fn read_value(&mut self) -> Result<Option<ConfigValue>, (usize, String)> {
+        if self.consume('[') {
+            let mut values = Vec::new();
+            if self.consume(']') {
+                return Ok(Some(ConfigValue::List(values)));
+            }
+            loop {
+                match self.read_scalar()? {
+                    Some(value) => values.push(value),
+                    None => return Err(self.error("arrays cannot hold null")),
+                }
+                if self.consume(']') {
+                    return Ok(Some(ConfigValue::List(values)));
+                }
+                self.expect(',')?;
+            }
+        }
+        Ok(self.read_scalar()?.map(ConfigValue::Scalar))
+    }
+
+    /// A string, number, `true` or `false` as its text, `None` for `null`.
+    fn read_scalar(&mut self) -> Result<Option<String>, (usize, String)> {
+        self.skip_whitespace();
+        if self.peek() == Some('"') {
+            return self.read_string().map(Some);
+        }
+        let rest = &self.text[self.offset..];
+        let end = rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || matches!(c, '-' | '+' | '.')))
+            .unwrap_or(rest.len());
+        let word = &rest[..end];
+        let is_number = word.starts_with(|c: char| c == '-' || c.is_ascii_digit())
+            && word.parse::<f64>().is_ok();
+        if word == "null" {
+            self.offset += end;
+            Ok(None)
+        } else if word == "true" || word == "false" || is_number {
+            self.offset += end;
+            Ok(Some(word.to_string()))
+        } else {
+            Err(self.error("expected a value"))
+        }
+    }
+
+    fn read_string(&mut self) -> Result<String, (usize, String)> {
+        self.expect('"')?;
+        let mut value = String::new();
+        loop {
+            let Some(c) = self.next() else {
+                return Err(self.error("unterminated string"));
+            };
+            match c {
+                '"' => return Ok(value),
+                '\\' => {
+                    let escaped = match self.next() {
+                        Some('n') => '\n',
+                        Some('t') => '\t',
+                        Some('r') => '\r',
+                        Some('b') => '\u{8}',
+                        Some('f') => '\u{c}',
+                        Some('u') => self.read_unicode_escape()?,
+                        Some(other @ ('"' | '\\' | '/')) => other,
+                        _ => return Err(self.error("invalid escape")),
+                    };
+                    value.push(escaped);
+                }
+                _ => value.push(c),
+            }
+        }
+    }
+
+    fn read_unicode_escape(&mut self) -> Result<char, (usize, String)> {
+        let digits = self.text.get(self.offset..self.offset + 4);
+        let code = digits.and_then(|digits| u32::from_str_radix(digits, 16).ok());
+        match code.and_then(char::from_u32) {
+            Some(c) => {
+                self.offset += 4;
+                Ok(c)
+            }
+            None => Err(self.error("invalid unicode escape")),
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = &self.text[self.offset..];
+        self.offset += rest.len() - rest.trim_start().len();
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.text[self.offset..].chars().next()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.offset += c.len_utf8();
+        Some(c)
+    }
+
+    /// Skips whitespace and `expected`, if it comes next.
+    fn consume(&mut self, expected: char) -> bool {
+        self.skip_whitespace();
+        if self.peek() == Some(expected) {
+            self.offset += expected.len_utf8();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), (usize, String)> {
+        if self.consume(expected) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected '{}'", expected)))
+        }
+    }
+
+    /// The line of the current offset, counting from one.
+    fn line(&self) -> usize {
+        self.text[..self.offset].matches('\n').count() + 1
+    }
+
+    /// A failure at the current offset.
+    fn error(&self, reason: &str) -> (usize, String) {
+        (self.line(), reason.to_string())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn section(path: &[&str]) -> Vec<String> {
+        path.iter().map(|part| part.to_string()).collect()
+    }
+
+    fn scalar(value: &str) -> Option<ConfigValue> {
+        Some(ConfigValue::Scalar(value.to_string()))
+    }
+
+    #[test]
+    fn parse_toml_sections_and_values() {
+        let text = r#"
+# shared settings
+timeout = 30
+name = "my tool" # trailing comment
+
+[serve]
+hosts = ["a", 'b', c]
+dry-run = true
+
+[remote.add]
+url = "https://example.com/#top"
+"#;
+        let config = ConfigFile::parse("tool.toml", text).unwrap();
+        assert_eq!(config.get(&[], "timeout").cloned(), scalar("30"));
+        assert_eq!(config.get(&[], "name").cloned(), scalar("my tool"));
+        assert_eq!(
+            config.get(&section(&["serve"]), "hosts").cloned(),
+            Some(ConfigValue::List(vec![
+                "a".to_string(),
+                "b".to_string(),
+                "c".to_string()
+            ]))
+        );
+        assert_eq!(
+            config.get(&section(&["remote", "add"]), "url").cloned(),
+            scalar("https://example.com/#top")
+        );
+        assert_eq!(config.get(&section(&["serve"]), "timeout").cloned(), None);
+        assert_eq!(
+            config
+                .subsections(&section(&["remote"]))
+                .collect::<Vec<_>>(),
+            ["add"]
+        );
+    }
+
+    #[test]
+    fn parse_ini_colons_and_semicolon_comments() {
+        let text = "; defaults\n[serve]\nport: 8080 ; http\n";
+        let config = ConfigFile::parse("tool.ini", text).unwrap();
+        assert_eq!(
+            config.get(&section(&["serve"]), "port").cloned(),
+            scalar("8080")
+        );
+    }
+
+    #[test]
+    fn parse_json_nested_objects_as_sections() {
+        let text = r#"{
+            "timeout": 30,
+            "verbose": true,
+            "region": null,
+            "serve": { "hosts": ["a", "b"], "name": "café" }
+        }"#;
+        let config = ConfigFile::parse("tool.json", text).unwrap();
+        assert_eq!(config.get(&[], "timeout").cloned(), scalar("30"));
+        assert_eq!(config.get(&[], "verbose").cloned(), scalar("true"));
+        assert_eq!(config.get(&[], "region").cloned(), None);
+        assert_eq!(
+            config.get(&section(&["serve"]), "name").cloned(),
+            scalar("café")
+        );
+        assert_eq!(
+            config.keys(&section(&["serve"])).collect::<Vec<_>>(),
+            [("hosts", 5), ("name", 5)]
+        );
+    }
+
+    #[test]
+    fn syntax_errors_name_the_line() {
+        for (path, text) in [
+            ("tool.toml", "a = 1\n\nb = \"open\n"),
+            ("tool.json", "{\n  \"a\": 1,\n  \"b\": oops\n}"),
+        ] {
+            match ConfigFile::parse(path, text) {
+                Err(ParseError::ConfigFailed { line, .. }) => assert_eq!(line, Some(3)),
+                other => panic!("unexpected result for {}: {:?}", path, other.err()),
+            }
+        }
+    }
+}
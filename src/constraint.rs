@@ -0,0 +1,78 @@
+use crate::command_line_parsing_results::CmdParsingResults;
+
+/// A rule between two arguments, named as in the results, about which of
+/// them were given on the command line.
+pub(crate) enum Constraint {
+    /// `argument` may only be given together with `required`.
+    Requires { argument: String, required: String },
+    /// `argument` and `other` may not be given together by the same layer,
+    /// one from a lower layer is overridden by the other.
+    ConflictsWith { argument: String, other: String },
+    /// `argument` must be given unless `unless` is.
+    RequiredUnless { argument: String, unless: String },
+}
+
+impl Constraint {
+    /// Whether the rule holds for the complete results, so arguments of
+    /// parent and sub-actions can be referred to alike.
+    pub(crate) fn holds(&self, result: &CmdParsingResults) -> bool {
+        match self {
+            Constraint::Requires { argument, required } => {
+                !result.is_given(argument) || result.is_given(required)
+            }
+            Constraint::ConflictsWith { argument, other } => {
+                !(result.is_given(argument) && result.is_given(other))
+                    || result.value_source(argument) != result.value_source(other)
+            }
+            Constraint::RequiredUnless { argument, unless } => {
+                result.is_given(argument) || result.is_given(unless)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::command_line_parsing_results::ValueSource;
+
+    #[test]
+    fn defaults_do_not_count_as_given() {
+        let constraint = Constraint::Requires {
+            argument: "key".to_string(),
+            required: "cert".to_string(),
+        };
+        let mut result = CmdParsingResults::new();
+        result.add_typed_result_value("key".to_string(), Box::new(true), None);
+        result.add_typed_result_value("cert".to_string(), Box::new(false), None);
+        assert!(constraint.holds(&result));
+        result.set_value_source("cert", ValueSource::Default);
+        assert!(!constraint.holds(&result));
+    }
+
+    #[test]
+    fn conflicts_only_within_one_layer() {
+        let constraint = Constraint::ConflictsWith {
+            argument: "port".to_string(),
+            other: "socket".to_string(),
+        };
+        let mut result = CmdParsingResults::new();
+        result.add_typed_result_value("port".to_string(), Box::new(80), None);
+        result.add_typed_result_value("socket".to_string(), Box::new(1), None);
+        assert!(!constraint.holds(&result));
+        result.set_value_source("port", ValueSource::Environment);
+        assert!(constraint.holds(&result));
+    }
+
+    #[test]
+    fn required_unless_accepts_either_argument() {
+        let constraint = Constraint::RequiredUnless {
+            argument: "password".to_string(),
+            unless: "config".to_string(),
+        };
+        let mut result = CmdParsingResults::new();
+        assert!(!constraint.holds(&result));
+        result.add_typed_result_value("config".to_string(), Box::new(true), None);
+        assert!(constraint.holds(&result));
+    }
+}
@@ -1,25 +1,29 @@
-use crate::command_line_parsing_results::CmdParsingResults;
-use std::any::Any;
+use crate::ValueParser;
+use crate::command_line_parsing_results::{CmdParsingResults, ValueSource};
+use crate::parse_error::ParseError;
 
 pub(crate) struct DefaultArgument {
     name: String,
     value: String,
-    parser: fn(&String) -> Box<dyn Any>,
+    parser: ValueParser,
+    type_name: Option<&'static str>,
 }
 
 impl DefaultArgument {
-    pub(crate) fn new(
-        name: String,
-        value: String,
-        parser: fn(&String) -> Box<dyn Any>,
-    ) -> DefaultArgument {
+    pub(crate) fn new(name: String, value: String, parser: ValueParser) -> DefaultArgument {
         DefaultArgument {
             name,
             value,
             parser,
+            type_name: None,
         }
     }
 
+    /// Records the Rust type produced by the parser, for type mismatch errors.
+    pub(crate) fn set_type_name(&mut self, type_name: &'static str) {
+        self.type_name = Some(type_name);
+    }
+
     #[allow(dead_code)]
     pub(crate) fn help(&self) -> String {
         String::from("must not be displayed")
@@ -29,9 +33,21 @@ impl DefaultArgument {
         &self,
         result: &mut CmdParsingResults,
         cmdline: &'b [String],
-    ) -> Result<&'b [String], String> {
-        result.add_result_value(self.name.clone(), (self.parser)(&self.value));
-        Ok(cmdline)
+    ) -> Result<&'b [String], ParseError> {
+        match (self.parser)(&self.value) {
+            Ok(parsed) => {
+                result.add_typed_result_value(self.name.clone(), parsed, self.type_name);
+                result.set_value_source(&self.name, ValueSource::Default);
+                Ok(cmdline)
+            }
+            Err(reason) => Err(ParseError::ConversionFailed {
+                argument: self.name.clone(),
+                value: self.value.clone(),
+                reason,
+                position: None,
+                context: Box::default(),
+            }),
+        }
     }
 }
 
@@ -43,7 +59,7 @@ mod test {
     fn parse_default_argument_leaves_cmdline_unchanged() {
         let cmdline = ["does".to_string(), "not".to_string(), "matter".to_string()];
         let default = DefaultArgument::new("test".to_string(), "test".to_string(), |val| {
-            Box::new(val.clone())
+            Ok(Box::new(val.clone()))
         });
         let mut result: CmdParsingResults = CmdParsingResults::new();
         match default.parse(&mut result, &cmdline[..]) {
@@ -55,26 +71,35 @@ mod test {
     #[test]
     fn parse_default_argument_stores_converted_value() {
         let cmdline: &[String] = &[];
-        let default = DefaultArgument::new(
-            "count".to_string(),
-            "42".to_string(),
-            |val| Box::new(val.parse::<i32>().expect("default value must be a number")),
-        );
+        let default = DefaultArgument::new("count".to_string(), "42".to_string(), |val| {
+            Ok(Box::new(val.parse::<i32>().map_err(|e| e.to_string())?))
+        });
         let mut result: CmdParsingResults = CmdParsingResults::new();
         default.parse(&mut result, cmdline).unwrap();
         assert_eq!(*result.get_value::<i32>("count"), 42);
     }
 
     #[test]
-    #[should_panic(expected = "default value must be a number")]
-    fn parse_default_argument_panics_on_bad_conversion() {
+    fn parse_default_argument_reports_bad_conversion() {
         let cmdline: &[String] = &[];
-        let default = DefaultArgument::new(
-            "count".to_string(),
-            "not-a-number".to_string(),
-            |val| Box::new(val.parse::<i32>().expect("default value must be a number")),
-        );
+        let default =
+            DefaultArgument::new("count".to_string(), "not-a-number".to_string(), |val| {
+                Ok(Box::new(val.parse::<i32>().map_err(|e| e.to_string())?))
+            });
         let mut result: CmdParsingResults = CmdParsingResults::new();
-        default.parse(&mut result, cmdline).unwrap();
+        match default.parse(&mut result, cmdline) {
+            Err(ParseError::ConversionFailed {
+                argument,
+                value,
+                position,
+                ..
+            }) => {
+                assert_eq!(argument, "count");
+                assert_eq!(value, "not-a-number");
+                assert_eq!(position, None);
+            }
+            Err(e) => panic!("unexpected error {:?}", e),
+            Ok(_) => panic!("should not have parsed"),
+        }
     }
 }
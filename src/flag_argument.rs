@@ -1,10 +1,18 @@
-use crate::command_line_parsing_results::CmdParsingResults;
+use crate::EnvLookup;
+use crate::command_line_parsing_results::{CmdParsingResults, ValueSource};
+use crate::config_file::ConfigValue;
+use crate::env_suffix;
+use crate::parse_error::ParseError;
+use crate::token::Token;
+use std::any::type_name;
 
 pub(crate) struct FlagArgument {
     name: String,
     long: String,
     short: char,
     doc: String,
+    count: bool,
+    env: Option<String>,
 }
 
 impl FlagArgument {
@@ -12,53 +20,176 @@ impl FlagArgument {
         self.name.as_str()
     }
 
+    pub(crate) fn long(&self) -> &str {
+        self.long.as_str()
+    }
+
     pub fn short(&self) -> char {
         self.short
     }
 
+    pub(crate) fn doc(&self) -> &str {
+        &self.doc
+    }
+
     pub(crate) fn new(name: String, long: String, short: char, doc: String) -> FlagArgument {
         FlagArgument {
             name,
             long,
             short,
             doc,
+            count: false,
+            env: None,
         }
     }
 
+    /// Counts the occurrences as a `u8` instead of storing `true`.
+    pub(crate) fn set_count(&mut self) {
+        self.count = true;
+    }
+
+    pub(crate) fn counts(&self) -> bool {
+        self.count
+    }
+
+    /// Reads the flag from the environment variable `var` when it is not
+    /// given on the command line.
+    pub(crate) fn set_env(&mut self, var: String) {
+        self.env = Some(var);
+    }
+
     pub(crate) fn help(&self) -> String {
         let name = format!("-{},--{}", self.short, self.long);
-        let spaces = 22 - name.len();
+        let spaces = 22_usize.saturating_sub(name.len()).max(1);
         name + String::from_utf8(vec![b' '; spaces])
             .expect("should be a string of whitespaces")
             .as_str()
             + &self.doc
+            + &env_suffix(&self.env)
+    }
+
+    /// Stores the flag from its environment variable, if `lookup` finds it, see
+    /// [`FlagArgument::store_text`].
+    pub(crate) fn store_environment(
+        &self,
+        result: &mut CmdParsingResults,
+        lookup: EnvLookup,
+    ) -> Result<(), ParseError> {
+        match self.env.as_deref().and_then(lookup) {
+            Some(value) => self.store_text(result, &value, ValueSource::Environment),
+            None => Ok(()),
+        }
+    }
+
+    /// Stores the flag from its value in a config file, see
+    /// [`FlagArgument::store_text`]. Arrays are rejected.
+    pub(crate) fn store_config(
+        &self,
+        result: &mut CmdParsingResults,
+        value: &ConfigValue,
+    ) -> Result<(), ParseError> {
+        match value {
+            ConfigValue::Scalar(text) => self.store_text(result, text, ValueSource::ConfigFile),
+            ConfigValue::List(items) => {
+                Err(self.invalid(&items.join(" "), "expected true or false"))
+            }
+        }
+    }
+
+    /// Stores `true` for a truthy value (`1`, `true`, `yes` or `on`) and
+    /// `false` for a falsy one (`0`, `false`, `no`, `off` or nothing), a
+    /// counting flag stores a count instead. Either way a later layer does not
+    /// override it, but a flag turned off does not count as given.
+    fn store_text(
+        &self,
+        result: &mut CmdParsingResults,
+        value: &str,
+        source: ValueSource,
+    ) -> Result<(), ParseError> {
+        let count = match value.trim().to_lowercase().as_str() {
+            "1" | "true" | "yes" | "on" => 1,
+            "" | "0" | "false" | "no" | "off" => 0,
+            number if self.count => number.parse::<u8>().map_err(|e| self.invalid(value, e))?,
+            _ => return Err(self.invalid(value, "expected true or false")),
+        };
+        if self.count {
+            result.add_typed_result_value(
+                self.name.clone(),
+                Box::new(count),
+                Some(type_name::<u8>()),
+            );
+        } else {
+            result.add_typed_result_value(
+                self.name.clone(),
+                Box::new(count != 0),
+                Some(type_name::<bool>()),
+            );
+        }
+        result.set_value_source(&self.name, source);
+        if count == 0 {
+            result.set_turned_off(&self.name);
+        }
+        Ok(())
     }
 
+    fn invalid(&self, value: &str, reason: impl ToString) -> ParseError {
+        ParseError::ConversionFailed {
+            argument: self.name.clone(),
+            value: value.to_string(),
+            reason: reason.to_string(),
+            position: None,
+            context: Box::default(),
+        }
+    }
+
+    /// Consumes the switch at the front of `cmdline`. A value attached to
+    /// the long name is an error, a short name followed by more characters is
+    /// not this flag.
     pub(crate) fn parse<'a>(
         &self,
         result: &mut CmdParsingResults,
         cmdline: &'a [String],
-    ) -> Result<&'a [String], String> {
-        match cmdline.first() {
-            Some(peeked_name) => {
-                // a flag needs at least two chars, e.g. -f
-                if peeked_name.len() >= 2 {
-                    // if the name matches either long or short
-                    if peeked_name[2..] == self.long
-                        || peeked_name[1..]
-                            .chars()
-                            .next()
-                            .expect("there needs to be a char")
-                            == self.short
-                    {
-                        // add the true value to the results
-                        result.add_result_value(self.name.clone(), Box::new(true));
-                        return Ok(&cmdline[1..]);
-                    }
-                }
-                Ok(cmdline)
+    ) -> Result<&'a [String], ParseError> {
+        let token = match cmdline.first() {
+            Some(peeked) => Token::classify(peeked),
+            None => return Ok(cmdline),
+        };
+        match token {
+            Token::Long {
+                name,
+                value: Some(value),
+            } if name == self.long => Err(ParseError::UnexpectedValue {
+                argument: self.name.clone(),
+                value: value.to_string(),
+                position: 0,
+                context: Box::default(),
+            }),
+            _ if token.matches(&self.long, self.short) && token.attached_value().is_none() => {
+                self.store(result);
+                Ok(&cmdline[1..])
             }
-            None => Ok(cmdline),
+            _ => Ok(cmdline),
+        }
+    }
+
+    /// Adds `true`, or one more occurrence when counting, to the results.
+    pub(crate) fn store(&self, result: &mut CmdParsingResults) {
+        if self.count {
+            let count = result
+                .get_optional_value::<u8>(&self.name)
+                .copied()
+                .unwrap_or_default();
+            result.add_typed_result_value(
+                self.name.clone(),
+                Box::new(count.saturating_add(1)),
+                Some(type_name::<u8>()),
+            );
+        } else {
+            result.add_typed_result_value(
+                self.name.clone(),
+                Box::new(true),
+                Some(type_name::<bool>()),
+            );
         }
     }
 }
@@ -81,9 +212,11 @@ mod test {
         let cmdline: &[String] = &["--test".to_string(), "chaff".to_string()];
         let mut result: CmdParsingResults = CmdParsingResults::new();
         let optional: FlagArgument = get_flag();
-        let remaining = optional.parse(&mut result, cmdline)?;
+        let remaining = optional
+            .parse(&mut result, cmdline)
+            .map_err(|e| e.to_string())?;
         if remaining == ["chaff".to_string()] {
-            if result.get_flag(&*optional.name) {
+            if result.get_flag(&optional.name) {
                 Ok(())
             } else {
                 Err("did not extract value as value".to_string())
@@ -98,9 +231,11 @@ mod test {
         let cmdline: &[String] = &["-t".to_string(), "chaff".to_string()];
         let mut result: CmdParsingResults = CmdParsingResults::new();
         let optional: FlagArgument = get_flag();
-        let remaining = optional.parse(&mut result, cmdline)?;
+        let remaining = optional
+            .parse(&mut result, cmdline)
+            .map_err(|e| e.to_string())?;
         if remaining == ["chaff".to_string()] {
-            if result.get_flag(&*optional.name) {
+            if result.get_flag(&optional.name) {
                 Ok(())
             } else {
                 Err("did not extract value as value".to_string())
@@ -118,12 +253,27 @@ mod test {
         let cmdline: &[String] = &["--other".to_string(), "chaff".to_string()];
         let mut result: CmdParsingResults = CmdParsingResults::new();
         let flag: FlagArgument = get_flag();
-        let remaining = flag.parse(&mut result, cmdline)?;
+        let remaining = flag
+            .parse(&mut result, cmdline)
+            .map_err(|e| e.to_string())?;
         assert_eq!(remaining, cmdline);
         assert!(!result.keys().contains(&&"test".to_string()));
         Ok(())
     }
 
+    #[test]
+    fn parse_counting_flag_repeatedly() {
+        let cmdline: &[String] = &["-t".to_string(), "--test".to_string(), "-t".to_string()];
+        let mut result: CmdParsingResults = CmdParsingResults::new();
+        let mut flag: FlagArgument = get_flag();
+        flag.set_count();
+        let mut remaining = cmdline;
+        while !remaining.is_empty() {
+            remaining = flag.parse(&mut result, remaining).unwrap();
+        }
+        assert_eq!(*result.get_value::<u8>("test"), 3);
+    }
+
     #[test]
     fn proper_help_msg_line() {
         let optional: FlagArgument = get_flag();
@@ -140,4 +290,31 @@ mod test {
         );
         assert_eq!(flag.help(), "-v,--verbose          enable verbose output");
     }
+
+    #[test]
+    fn help_of_long_flag_keeps_one_space() {
+        let flag = FlagArgument::new(
+            "include_hidden_files".to_string(),
+            "include-hidden-files".to_string(),
+            'i',
+            "list dot files".to_string(),
+        );
+        assert_eq!(flag.help(), "-i,--include-hidden-files list dot files");
+    }
+
+    #[test]
+    fn parse_flag_with_attached_value_fails() {
+        let cmdline: &[String] = &["--test=yes".to_string()];
+        let mut result: CmdParsingResults = CmdParsingResults::new();
+        match get_flag().parse(&mut result, cmdline) {
+            Err(ParseError::UnexpectedValue {
+                argument, value, ..
+            }) => {
+                assert_eq!(argument, "test");
+                assert_eq!(value, "yes");
+            }
+            Err(e) => panic!("unexpected error {:?}", e),
+            Ok(_) => panic!("should not have parsed"),
+        }
+    }
 }
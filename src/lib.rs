@@ -1,19 +1,92 @@
 #![doc = include_str!("../README.md")]
 
+extern crate self as rust_argparse;
+
+mod argument_group;
+mod choices;
 pub mod command_line_parsing_results;
+pub mod completion;
+mod config_file;
+mod constraint;
 mod default_argument;
 mod flag_argument;
+mod mutually_exclusive_group;
+pub mod nargs;
 mod optional_argument;
+pub mod parse_error;
 mod positional_argument;
+mod response_file;
+mod suggestion;
+#[cfg(test)]
+mod test_dir;
+mod token;
 
-use crate::command_line_parsing_results::CmdParsingResults;
+use crate::argument_group::ArgumentGroup;
+use crate::choices::Choices;
+use crate::command_line_parsing_results::{CmdParsingResults, FromResults, ValueSource};
+use crate::completion::{Candidate, Command, Shell, Switch};
+use crate::config_file::ConfigFile;
+use crate::constraint::Constraint;
 use crate::default_argument::DefaultArgument;
 use crate::flag_argument::FlagArgument;
+use crate::mutually_exclusive_group::MutuallyExclusiveGroup;
+use crate::nargs::Nargs;
 use crate::optional_argument::OptionalArgument;
+use crate::parse_error::{ErrorContext, ParseError};
 use crate::positional_argument::PositionalArgument;
-use std::any::Any;
+use crate::token::Token;
+use std::any::{Any, type_name};
 use std::cell::RefCell;
 use std::env;
+use std::fmt::Display;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+#[cfg(feature = "derive")]
+pub use rust_argparse_derive::ArgParse;
+
+/// Converts a command line token into the value stored in
+/// [`CmdParsingResults`]. An `Err` is reported as
+/// [`ParseError::ConversionFailed`], naming the argument and the token.
+pub type ValueParser = fn(&String) -> Result<Box<dyn Any>, String>;
+
+/// Lists the values a positional or optional may take, given the partially
+/// typed one, for dynamic completion; see [`Parser::completer`]. Candidates
+/// not starting with the partial value, outside of the argument's choices or
+/// already offered are dropped.
+pub type Completer = fn(&str) -> Vec<String>;
+
+/// The [`ValueParser`] behind the `add_typed_*` builders.
+#[allow(clippy::ptr_arg)] // must match the `ValueParser` signature
+fn parse_from_str<T>(value: &String) -> Result<Box<dyn Any>, String>
+where
+    T: FromStr + 'static,
+    T::Err: Display,
+{
+    match value.parse::<T>() {
+        Ok(parsed) => Ok(Box::new(parsed)),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Reads an environment variable, `None` if it is not set; see
+/// [`Parser::with_env_lookup`].
+pub type EnvLookup = fn(&str) -> Option<String>;
+
+/// The [`EnvLookup`] reading the environment of the process.
+fn process_env(var: &str) -> Option<String> {
+    env::var(var).ok()
+}
+
+/// The `[env: VAR]` note appended to the help of an argument bound to an
+/// environment variable.
+fn env_suffix(env: &Option<String>) -> String {
+    match env {
+        Some(var) => format!(" [env: {}]", var),
+        None => String::new(),
+    }
+}
 
 /// Converts a plain closure or named function into the boxed trait object
 /// stored by [`Parser`], so `with_main` never requires writing `Box::new`.
@@ -68,27 +141,93 @@ impl_into_main_with_results!(A, B, C);
 impl_into_main_with_results!(A, B, C, D);
 impl_into_main_with_results!(A, B, C, D, E);
 
+/// A type whose arguments are declared on a [`Parser`] and read back from
+/// its [`CmdParsingResults`]: structs map their fields to positionals,
+/// optionals and flags, enums map their variants to sub-actions. With the
+/// `derive` cargo feature it can be derived with `#[derive(ArgParse)]`,
+/// which implements [`FromResults`] as well.
+pub trait ArgParse: FromResults {
+    /// The name of the parser built by [`ArgParse::parser`].
+    const NAME: &'static str;
+    /// The doc of the parser built by [`ArgParse::parser`].
+    const DOC: &'static str;
+
+    /// Adds the arguments (or sub-actions) of `Self` to `parser`.
+    fn augment_parser<F: ?Sized + 'static>(parser: Parser<F>) -> Parser<F>;
+
+    /// Builds `Self` from the results of a parser set up by `augment_parser`,
+    /// panicking with every missing or mistyped value if that fails.
+    fn from_results(results: &CmdParsingResults) -> Self {
+        match results.extract::<Self>() {
+            Ok(value) => value,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    fn parser<F: ?Sized + 'static>() -> Parser<F> {
+        Self::augment_parser(Parser::new(Self::NAME, Self::DOC))
+    }
+}
+
 pub struct Parser<F: ?Sized + 'static> {
     name: String,
+    aliases: Vec<String>,
     doc: String,
     defaults: Vec<DefaultArgument>,
     actions: Vec<Parser<F>>,
     positionals: Vec<PositionalArgument>,
     optionals: Vec<OptionalArgument>,
     flags: Vec<FlagArgument>,
+    exclusive_groups: Vec<MutuallyExclusiveGroup>,
+    argument_groups: Vec<ArgumentGroup>,
+    constraints: Vec<Constraint>,
+    config_files: Vec<String>,
+    fromfile_prefix: Option<char>,
+    last_added: Option<ArgumentKind>,
+    allow_abbrev: bool,
+    allow_action_abbrev: bool,
+    inherit_main: bool,
+    env_lookup: EnvLookup,
     main: RefCell<Option<Box<F>>>,
 }
 
+/// A block of the help body, titled unless it holds the ungrouped arguments.
+struct HelpSection<'a> {
+    title: Option<&'a str>,
+    description: &'a str,
+    lines: Vec<String>,
+}
+
+/// The kind of the most recently added argument, which the modifiers (e.g.
+/// [`Parser::append`]) apply to.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum ArgumentKind {
+    Positional,
+    Optional,
+    Flag,
+}
+
 impl<F: ?Sized + 'static> Parser<F> {
     pub fn new(name: &str, doc: &str) -> Parser<F> {
         Parser {
             name: name.to_string(),
+            aliases: Vec::new(),
             doc: doc.to_string(),
             defaults: Vec::new(),
             actions: Vec::new(),
             positionals: Vec::new(),
             optionals: Vec::new(),
             flags: Vec::new(),
+            exclusive_groups: Vec::new(),
+            argument_groups: Vec::new(),
+            constraints: Vec::new(),
+            config_files: Vec::new(),
+            fromfile_prefix: None,
+            last_added: None,
+            allow_abbrev: false,
+            allow_action_abbrev: false,
+            inherit_main: false,
+            env_lookup: process_env,
             main: RefCell::new(None),
         }
     }
@@ -98,6 +237,74 @@ impl<F: ?Sized + 'static> Parser<F> {
         self
     }
 
+    /// Lets this parser, as a leaf action without a main of its own, run the
+    /// main of the closest ancestor that has one. Without it such a leaf
+    /// panics when selected. Derived enums set it on their variants, so a
+    /// derived tree needs just one main, on its root.
+    pub fn inherit_main(mut self) -> Parser<F> {
+        self.inherit_main = true;
+        self
+    }
+
+    /// Accepts a unique prefix of a long switch, so `--verb` reads
+    /// `--verbose`, like argparse's `allow_abbrev`. Exact names always win, a
+    /// prefix of several names fails with [`ParseError::AmbiguousOption`].
+    /// Applies to this parser's own optionals and flags only.
+    pub fn allow_abbrev(mut self) -> Parser<F> {
+        self.allow_abbrev = true;
+        self
+    }
+
+    /// Reads the config file at `path`, if it exists, to fill the optionals
+    /// and flags missing from the command line and the environment, before
+    /// their defaults. Keys are long names (or argument names); a
+    /// `[section.sub]` holds those of the sub-action `section sub`. Files
+    /// ending in `.json` are read as JSON objects, with nested objects as
+    /// sections, others as `key = value` lines like TOML or INI. Files added
+    /// later take precedence.
+    pub fn with_config_file(mut self, path: &str) -> Parser<F> {
+        self.config_files.push(path.to_string());
+        self
+    }
+
+    /// Replaces every token starting with `prefix`, as in `@build.args`, by
+    /// the tokens read from the file it names before parsing, like argparse's
+    /// `fromfile_prefix_chars`. Files are split like a shell would, so tokens
+    /// holding spaces need quotes, but a backslash only escapes whitespace,
+    /// quotes, `#` and itself. Files may name further files, up to 8 levels
+    /// deep. Failures, cycles included, are reported as
+    /// [`ParseError::FromFileFailed`]; positions in other errors refer to the
+    /// expanded command line. Only applies to the parser `parse` is called on.
+    pub fn fromfile_prefix(mut self, prefix: char) -> Parser<F> {
+        self.fromfile_prefix = Some(prefix);
+        self
+    }
+
+    /// Reads the environment variables bound with [`Parser::env`] through
+    /// `lookup` instead of from the process, e.g. to parse against a fixed
+    /// environment in tests. Applies to the whole tree, but only when set on
+    /// the parser `parse` is called on.
+    pub fn with_env_lookup(mut self, lookup: EnvLookup) -> Parser<F> {
+        self.env_lookup = lookup;
+        self
+    }
+
+    /// Adds another name this parser can be invoked by as a sub-action, e.g.
+    /// `rm` for `remove`. The results still record the parser's own name.
+    pub fn alias(mut self, alias: &str) -> Parser<F> {
+        self.aliases.push(alias.to_string());
+        self
+    }
+
+    /// Accepts a unique prefix of a sub-action's name or alias, so `rem`
+    /// selects `remove`, where the token would otherwise not match, so
+    /// positionals that can still take it keep it as a value. A prefix of
+    /// several actions fails with [`ParseError::AmbiguousAction`].
+    pub fn allow_action_abbrev(mut self) -> Parser<F> {
+        self.allow_action_abbrev = true;
+        self
+    }
+
     #[allow(unused)]
     pub fn add_action(mut self, parser: Parser<F>) -> Parser<F> {
         self.actions.push(parser);
@@ -106,7 +313,7 @@ impl<F: ?Sized + 'static> Parser<F> {
 
     #[allow(unused)]
     pub fn add_default(self, name: String, value: String) -> Parser<F> {
-        self.add_parsed_default(name, value, |val: &String| Box::new(val.clone()))
+        self.add_typed_default::<String>(name, value)
     }
 
     #[allow(unused)]
@@ -114,21 +321,38 @@ impl<F: ?Sized + 'static> Parser<F> {
         mut self,
         name: String,
         value: String,
-        parser: fn(&String) -> Box<dyn Any>,
+        parser: ValueParser,
     ) -> Parser<F> {
         self.defaults
             .push(DefaultArgument::new(name, value, parser));
         self
     }
 
+    /// Like [`Parser::add_parsed_default`], with the value converted by `T`'s
+    /// `FromStr` implementation.
+    #[allow(unused)]
+    pub fn add_typed_default<T>(self, name: String, value: String) -> Parser<F>
+    where
+        T: FromStr + 'static,
+        T::Err: Display,
+    {
+        let mut parser = self.add_parsed_default(name, value, parse_from_str::<T>);
+        parser
+            .defaults
+            .last_mut()
+            .expect("default was just added")
+            .set_type_name(type_name::<T>());
+        parser
+    }
+
     pub fn add_positional(self, name: &str, doc: &str) -> Parser<F> {
-        self.add_parsed_positional(name, |val: &String| Box::new(val.clone()), doc)
+        self.add_typed_positional::<String>(name, doc)
     }
 
     pub fn add_parsed_positional(
         mut self,
         name: &str,
-        parser: fn(&String) -> Box<dyn Any>,
+        parser: ValueParser,
         doc: &str,
     ) -> Parser<F> {
         self.positionals.push(PositionalArgument::new(
@@ -136,9 +360,26 @@ impl<F: ?Sized + 'static> Parser<F> {
             parser,
             doc.to_string(),
         ));
+        self.last_added = Some(ArgumentKind::Positional);
         self
     }
 
+    /// Like [`Parser::add_parsed_positional`], with the value converted by
+    /// `T`'s `FromStr` implementation.
+    pub fn add_typed_positional<T>(self, name: &str, doc: &str) -> Parser<F>
+    where
+        T: FromStr + 'static,
+        T::Err: Display,
+    {
+        let mut parser = self.add_parsed_positional(name, parse_from_str::<T>, doc);
+        parser
+            .positionals
+            .last_mut()
+            .expect("positional was just added")
+            .set_type_name(type_name::<T>());
+        parser
+    }
+
     #[allow(unused)]
     pub fn add_optional(
         self,
@@ -148,7 +389,7 @@ impl<F: ?Sized + 'static> Parser<F> {
         default: Option<&str>,
         doc: &str,
     ) -> Parser<F> {
-        self.add_parsed_optional(name, long, short, default, |val| Box::new(val.clone()), doc)
+        self.add_typed_optional::<String>(name, long, short, default, doc)
     }
 
     pub fn add_parsed_optional(
@@ -157,13 +398,10 @@ impl<F: ?Sized + 'static> Parser<F> {
         long: &str,
         short: char,
         default: Option<&str>,
-        parser: fn(&String) -> Box<dyn Any>,
+        parser: ValueParser,
         doc: &str,
     ) -> Parser<F> {
-        let conv_default = match default {
-            Some(str) => Some(str.to_string()),
-            None => None,
-        };
+        let conv_default = default.map(|str| str.to_string());
         self.optionals.push(OptionalArgument::new(
             name.to_string(),
             long.to_string(),
@@ -172,9 +410,34 @@ impl<F: ?Sized + 'static> Parser<F> {
             parser,
             doc.to_string(),
         ));
+        self.last_added = Some(ArgumentKind::Optional);
         self
     }
 
+    /// Like [`Parser::add_parsed_optional`], with the value (and `default`)
+    /// converted by `T`'s `FromStr` implementation.
+    pub fn add_typed_optional<T>(
+        self,
+        name: &str,
+        long: &str,
+        short: char,
+        default: Option<&str>,
+        doc: &str,
+    ) -> Parser<F>
+    where
+        T: FromStr + 'static,
+        T::Err: Display,
+    {
+        let mut parser =
+            self.add_parsed_optional(name, long, short, default, parse_from_str::<T>, doc);
+        parser
+            .optionals
+            .last_mut()
+            .expect("optional was just added")
+            .set_type_name(type_name::<T>());
+        parser
+    }
+
     #[allow(unused)]
     pub fn add_flag(mut self, name: &str, long: &str, short: char, doc: &str) -> Parser<F> {
         self.flags.push(FlagArgument::new(
@@ -183,332 +446,2917 @@ impl<F: ?Sized + 'static> Parser<F> {
             short,
             doc.to_string(),
         ));
+        self.last_added = Some(ArgumentKind::Flag);
+        self
+    }
+
+    /// Adds the optionals and flags declared by `build` as a group of which at
+    /// most one may be given, or exactly one if `required`. Violations fail
+    /// with [`ParseError::MutuallyExclusive`] or, for a required group of
+    /// which none was given, [`ParseError::MissingRequired`].
+    pub fn add_mutually_exclusive_group(
+        self,
+        required: bool,
+        build: impl FnOnce(Parser<F>) -> Parser<F>,
+    ) -> Parser<F> {
+        let positionals = self.positionals.len();
+        let optionals = self.optionals.len();
+        let flags = self.flags.len();
+        let mut parser = build(self);
+        if parser.positionals.len() != positionals {
+            panic!("mutually exclusive groups hold only optionals and flags");
+        }
+        let members = parser.optionals[optionals..]
+            .iter()
+            .map(|optional| optional.name())
+            .chain(
+                parser.flags[flags..]
+                    .iter()
+                    .map(|flag| flag.name().to_string()),
+            )
+            .collect();
+        parser
+            .exclusive_groups
+            .push(MutuallyExclusiveGroup::new(members, required));
+        parser
+    }
+
+    /// Lists the arguments declared by `build` under `title` in the help,
+    /// followed by the `description` if it is not empty, after the arguments
+    /// and actions outside of any group. Argument groups cannot be nested.
+    pub fn add_argument_group(
+        self,
+        title: &str,
+        description: &str,
+        build: impl FnOnce(Parser<F>) -> Parser<F>,
+    ) -> Parser<F> {
+        let positionals = self.positionals.len();
+        let optionals = self.optionals.len();
+        let flags = self.flags.len();
+        let groups = self.argument_groups.len();
+        let mut parser = build(self);
+        if parser.argument_groups.len() != groups {
+            panic!("argument groups cannot be nested");
+        }
+        let members = parser.positionals[positionals..]
+            .iter()
+            .map(|positional| positional.name().to_string())
+            .chain(
+                parser.optionals[optionals..]
+                    .iter()
+                    .map(|optional| optional.name()),
+            )
+            .chain(
+                parser.flags[flags..]
+                    .iter()
+                    .map(|flag| flag.name().to_string()),
+            )
+            .collect();
+        parser.argument_groups.push(ArgumentGroup::new(
+            title.to_string(),
+            description.to_string(),
+            members,
+        ));
+        parser
+    }
+
+    /// Makes the most recently added optional collect every occurrence, so
+    /// `-I a -I b` stores both values, read with
+    /// [`CmdParsingResults::get_values`]. Without occurrences it holds its
+    /// default, if any, or nothing.
+    pub fn append(mut self) -> Parser<F> {
+        self.last_optional("append").set_append();
+        self
+    }
+
+    /// Makes the most recently added optional mandatory: parsing fails with
+    /// [`ParseError::MissingRequired`], listing every required optional that
+    /// was not given, instead of storing the default.
+    pub fn required(mut self) -> Parser<F> {
+        self.last_optional("required").set_required();
+        self
+    }
+
+    /// Makes the most recently added flag count its occurrences, so `-v -v -v`
+    /// stores `3u8` instead of `true`. Without occurrences it holds `0u8`.
+    pub fn count(mut self) -> Parser<F> {
+        self.last_flag("count").set_count();
+        self
+    }
+
+    /// Sets how many tokens the most recently added positional or optional
+    /// consumes. List cardinalities store every token, read with
+    /// [`CmdParsingResults::get_values`] or [`CmdParsingResults::get_array`].
+    ///
+    /// Bare tokens are split among the positionals so that later ones still
+    /// get their minimum, e.g. `cp SRC... DST` with [`Nargs::OneOrMore`]
+    /// followed by a plain positional. An optional takes exactly N tokens
+    /// after its switch for [`Nargs::Exactly`], otherwise the tokens up to the
+    /// next switch; its default is split on whitespace for list cardinalities.
+    pub fn nargs(mut self, nargs: Nargs) -> Parser<F> {
+        match self.last_added {
+            Some(ArgumentKind::Positional) => self.last_positional("nargs").set_nargs(nargs),
+            Some(ArgumentKind::Optional) => self.last_optional("nargs").set_nargs(nargs),
+            _ => panic!("nargs must directly follow a positional or an optional"),
+        }
+        self
+    }
+
+    /// Makes the value of the most recently added optional, a `String` or
+    /// `PathBuf`, name a config file read like those of
+    /// [`Parser::with_config_file`], which must exist and takes precedence
+    /// over them.
+    pub fn config_file(mut self) -> Parser<F> {
+        self.last_optional("config_file").set_config_file();
+        self
+    }
+
+    /// Binds the most recently added optional or flag to the environment
+    /// variable `var`, read when the switch is not given and shown in help as
+    /// `[env: VAR]`. An optional takes the value as if given after the switch,
+    /// a flag is set by `1`, `true`, `yes` or `on` and cleared by `0`,
+    /// `false`, `no`, `off` or an empty value. Either way config files and the
+    /// default only apply if the variable is not set.
+    pub fn env(mut self, var: &str) -> Parser<F> {
+        match self.last_added {
+            Some(ArgumentKind::Optional) => self.last_optional("env").set_env(var.to_string()),
+            Some(ArgumentKind::Flag) => self.last_flag("env").set_env(var.to_string()),
+            _ => panic!("env must directly follow an optional or a flag"),
+        }
         self
     }
 
-    pub fn parse_cmdline(&self) -> Result<(CmdParsingResults, Box<F>), String> {
+    /// Restricts the most recently added positional or optional to the given
+    /// tokens, shown in usage and help as `{debug,info,warn}`. Any other token
+    /// fails with [`ParseError::InvalidChoice`], listing the valid ones.
+    pub fn choices(mut self, choices: &[&str]) -> Parser<F> {
+        let choices = Choices::new(choices);
+        match self.last_added {
+            Some(ArgumentKind::Positional) => self.last_positional("choices").set_choices(choices),
+            Some(ArgumentKind::Optional) => self.last_optional("choices").set_choices(choices),
+            _ => panic!("choices must directly follow a positional or an optional"),
+        }
+        self
+    }
+
+    /// Completes the values of the most recently added positional or optional
+    /// with `completer`, on top of its choices, when the shell asks through
+    /// [`Parser::complete`].
+    pub fn completer(mut self, completer: Completer) -> Parser<F> {
+        match self.last_added {
+            Some(ArgumentKind::Positional) => {
+                self.last_positional("completer").set_completer(completer)
+            }
+            Some(ArgumentKind::Optional) => {
+                self.last_optional("completer").set_completer(completer)
+            }
+            _ => panic!("completer must directly follow a positional or an optional"),
+        }
+        self
+    }
+
+    /// Gives the most recently added optional the value stored when its
+    /// switch is given without one, as in `--color` for `--color [WHEN]`.
+    /// Only used with, and required by, [`Nargs::Optional`]; parsing panics up
+    /// front if an optional anywhere in the tree lacks it.
+    pub fn const_value(mut self, value: &str) -> Parser<F> {
+        self.last_optional("const_value")
+            .set_const_value(value.to_string());
+        self
+    }
+
+    /// Gives the most recently added positional a value for when no token is
+    /// left for it, converted like a given token. Only useful together with a
+    /// [`Parser::nargs`] that allows zero tokens.
+    pub fn default_value(mut self, value: &str) -> Parser<F> {
+        self.last_positional("default_value")
+            .set_default(value.to_string());
+        self
+    }
+
+    /// Lets the most recently added argument only be given together with
+    /// the one called `required`, which may belong to a parent action.
+    /// Violations fail with [`ParseError::MissingDependency`].
+    pub fn requires(mut self, required: &str) -> Parser<F> {
+        let argument = self.last_name("requires");
+        self.constraints.push(Constraint::Requires {
+            argument,
+            required: required.to_string(),
+        });
+        self
+    }
+
+    /// Forbids giving the most recently added argument together with the one
+    /// called `other`, which may belong to a parent action. Violations fail
+    /// with [`ParseError::MutuallyExclusive`].
+    pub fn conflicts_with(mut self, other: &str) -> Parser<F> {
+        let argument = self.last_name("conflicts_with");
+        self.constraints.push(Constraint::ConflictsWith {
+            argument,
+            other: other.to_string(),
+        });
+        self
+    }
+
+    /// Makes the most recently added argument mandatory unless the one called
+    /// `unless`, which may belong to a parent action, is given. Violations
+    /// fail with [`ParseError::MissingRequired`], listing both.
+    pub fn required_unless(mut self, unless: &str) -> Parser<F> {
+        let argument = self.last_name("required_unless");
+        self.constraints.push(Constraint::RequiredUnless {
+            argument,
+            unless: unless.to_string(),
+        });
+        self
+    }
+
+    fn last_name(&self, modifier: &str) -> String {
+        match self.last_added {
+            Some(ArgumentKind::Positional) => self
+                .positionals
+                .last()
+                .expect("positional was added")
+                .name()
+                .to_string(),
+            Some(ArgumentKind::Optional) => {
+                self.optionals.last().expect("optional was added").name()
+            }
+            Some(ArgumentKind::Flag) => self
+                .flags
+                .last()
+                .expect("flag was added")
+                .name()
+                .to_string(),
+            None => panic!("{} must directly follow an argument", modifier),
+        }
+    }
+
+    fn last_positional(&mut self, modifier: &str) -> &mut PositionalArgument {
+        match self.last_added {
+            Some(ArgumentKind::Positional) => {
+                self.positionals.last_mut().expect("positional was added")
+            }
+            _ => panic!("{} must directly follow a positional", modifier),
+        }
+    }
+
+    fn last_optional(&mut self, modifier: &str) -> &mut OptionalArgument {
+        match self.last_added {
+            Some(ArgumentKind::Optional) => self.optionals.last_mut().expect("optional was added"),
+            _ => panic!("{} must directly follow an optional", modifier),
+        }
+    }
+
+    fn last_flag(&mut self, modifier: &str) -> &mut FlagArgument {
+        match self.last_added {
+            Some(ArgumentKind::Flag) => self.flags.last_mut().expect("flag was added"),
+            _ => panic!("{} must directly follow a flag", modifier),
+        }
+    }
+
+    /// Parses the arguments of the process. If the `COMPLETE` environment
+    /// variable names a shell, it instead prints the script registering
+    /// dynamic completion in that shell, or, given `--` followed by the typed
+    /// words, the candidates of [`Parser::complete`], and exits.
+    pub fn parse_cmdline(&self) -> Result<(CmdParsingResults, Box<F>), ParseError> {
         let arg_slice = env::args().collect::<Vec<String>>();
+        if let Ok(shell) = env::var(completion::COMPLETE_VAR)
+            && !shell.is_empty()
+        {
+            self.answer_completion(&shell, &arg_slice[1..]);
+        }
         self.parse(arg_slice[1..].to_vec())
     }
 
-    pub fn parse(&self, cmdline_args: Vec<String>) -> Result<(CmdParsingResults, Box<F>), String> {
-        let mut result = CmdParsingResults::new();
-        result.set_action(self.name.clone());
-        match self.parse_tree(&mut result, &cmdline_args[..]) {
-            Ok((remaining, main)) => {
-                if remaining.is_empty() {
-                    Ok((result, main))
-                } else {
-                    Err(format!(
-                        "Too many cmd arguments after: {:?} \n\n {}",
-                        remaining,
-                        self.help()
-                    ))
+    /// Answers a completion request of the shell, see [`Parser::parse_cmdline`].
+    fn answer_completion(&self, shell: &str, args: &[String]) -> ! {
+        let mut out = io::stdout();
+        let written = match args.split_first() {
+            Some((separator, words)) if separator == "--" => self.complete(words, &mut out),
+            _ => match shell.parse::<Shell>() {
+                Ok(shell) => out.write_all(completion::register(shell, &self.name).as_bytes()),
+                Err(e) => {
+                    eprintln!("{}", e);
+                    std::process::exit(2)
+                }
+            },
+        };
+        std::process::exit(if written.is_ok() { 0 } else { 1 })
+    }
+
+    /// Writes the candidates completing the last of `words`, the partially
+    /// typed command line without the program name, one per line followed by
+    /// a tab and its doc if it has one. The words are scanned like by
+    /// [`Parser::parse`] to find the argument being completed: the values of
+    /// an optional after its switch, the switches after `-`, otherwise the
+    /// sub-actions and the values of the next positional. Values come from
+    /// the choices and the [`Parser::completer`].
+    pub fn complete(&self, words: &[String], out: &mut impl Write) -> io::Result<()> {
+        let candidates = match words.split_last() {
+            Some((current, typed)) => self.completion_candidates(typed, current, false),
+            None => self.completion_candidates(&[], "", false),
+        };
+        completion::write_candidates(&candidates, out)
+    }
+
+    /// The candidates for `current` after the `typed` tokens of this level.
+    fn completion_candidates(
+        &self,
+        typed: &[String],
+        current: &str,
+        mut options_ended: bool,
+    ) -> Vec<Candidate> {
+        let mut collected = 0;
+        let mut index = 0;
+        while let Some(token) = typed.get(index) {
+            index += 1;
+            if !options_ended {
+                match Token::classify(token) {
+                    Token::Separator => {
+                        options_ended = true;
+                        continue;
+                    }
+                    Token::Value(_) => {}
+                    Token::NegativeNumber(_) if !self.has_digit_shorts() => {}
+                    switch => {
+                        let Some(optional) = self
+                            .optionals
+                            .iter()
+                            .find(|item| switch.matches(item.long(), item.short()))
+                        else {
+                            continue;
+                        };
+                        let following = &typed[index..];
+                        let attached = switch.attached_value().is_some();
+                        let values = optional.count_values(following, attached);
+                        // the values run up to the token being completed, which
+                        // is another one unless it starts a switch that may follow
+                        if following.iter().all(|t| Token::classify(t).is_value())
+                            && optional.takes_another_value(following.len() + usize::from(attached))
+                            && (values.is_err() || Token::classify(current).is_value())
+                        {
+                            return completion::value_candidates(
+                                optional.choices(),
+                                optional.completer(),
+                                current,
+                            );
+                        }
+                        if let Ok(count) = values {
+                            index += count;
+                        }
+                        continue;
+                    }
+                }
+            }
+            if self.positionals_filled(collected, token) {
+                if let Some(action) = self.find_matching_action(token) {
+                    return action.completion_candidates(&typed[index..], current, options_ended);
                 }
+                continue;
             }
-            Err(msg) => Err(msg),
+            collected += 1;
+        }
+        if !options_ended && current.starts_with('-') {
+            return self.switch_candidates(current);
+        }
+        let mut candidates = Vec::new();
+        let minimum: usize = self.positionals.iter().map(|p| p.nargs().min()).sum();
+        if collected >= minimum {
+            for action in &self.actions {
+                candidates.extend(action.names().filter(|name| name.starts_with(current)).map(
+                    |name| Candidate {
+                        word: name.to_string(),
+                        doc: action.doc.clone(),
+                    },
+                ));
+            }
+        }
+        if let Some(positional) = self.next_positional(collected) {
+            candidates.extend(completion::value_candidates(
+                positional.choices(),
+                positional.completer(),
+                current,
+            ));
         }
+        candidates
     }
 
-    fn check_for_help(&self, cmdline: &[String]) -> Result<(), String> {
-        if !cmdline.is_empty() {
-            match cmdline.first().expect("remaining is not empty").as_str() {
-                "--help" | "-h" => Err(self.help()),
-                _ => Ok(()),
+    /// The switches starting with `current`, or the values of the optional
+    /// for `--name=value` and `-nvalue`.
+    fn switch_candidates(&self, current: &str) -> Vec<Candidate> {
+        let token = Token::classify(current);
+        if let Some(value) = token.attached_value()
+            && let Some(optional) = self
+                .optionals
+                .iter()
+                .find(|optional| token.matches(optional.long(), optional.short()))
+        {
+            let switch = &current[..current.len() - value.len()];
+            return completion::value_candidates(optional.choices(), optional.completer(), value)
+                .into_iter()
+                .map(|candidate| Candidate {
+                    word: format!("{}{}", switch, candidate.word),
+                    doc: candidate.doc,
+                })
+                .collect();
+        }
+        let optionals = self
+            .optionals
+            .iter()
+            .map(|item| (item.short(), item.long(), item.doc()));
+        let flags = self
+            .flags
+            .iter()
+            .map(|item| (item.short(), item.long(), item.doc()));
+        optionals
+            .chain(flags)
+            .flat_map(|(short, long, doc)| {
+                [format!("--{}", long), format!("-{}", short)].map(|word| Candidate {
+                    word,
+                    doc: doc.to_string(),
+                })
+            })
+            .filter(|candidate| candidate.word.starts_with(current))
+            .collect()
+    }
+
+    /// The positional receiving the next bare token after `collected` ones.
+    fn next_positional(&self, collected: usize) -> Option<&PositionalArgument> {
+        let mut before = 0;
+        for positional in &self.positionals {
+            match positional.nargs().max() {
+                Some(max) if before + max <= collected => before += max,
+                _ => return Some(positional),
             }
-        } else {
-            Ok(())
         }
+        None
     }
 
-    fn parse_default_arguments<'b>(
+    pub fn parse(
         &self,
-        result: &mut CmdParsingResults,
-        cmdline: &'b [String],
-    ) -> Result<&'b [String], String> {
-        let mut remaining_cmd_line = cmdline;
-        for (_, item) in self.defaults.iter().enumerate() {
-            match item.parse(result, remaining_cmd_line) {
-                Ok(remains) => remaining_cmd_line = remains,
-                Err(e) => return Err(format!("Bad Cmd Arguments: {} \n\n {}", e, self.help())),
+        cmdline_args: Vec<String>,
+    ) -> Result<(CmdParsingResults, Box<F>), ParseError> {
+        self.check_const_values();
+        let cmdline_args = match self.fromfile_prefix {
+            Some(prefix) => response_file::expand(cmdline_args, prefix)
+                .map_err(|e| e.in_context(0, std::slice::from_ref(&self.name), self.help()))?,
+            None => cmdline_args,
+        };
+        let mut result = CmdParsingResults::new();
+        let main = self
+            .parse_tree(&mut result, &cmdline_args[..], 0, false, self.env_lookup)?
+            .unwrap_or_else(|| {
+                panic!("leaf parser '{}' has no main function", result.get_action())
+            });
+        self.check_constraints(&result)?;
+        Ok((result, main))
+    }
+
+    /// Panics if an optional anywhere in the tree takes an optional value but
+    /// has no const value, whichever action the command line selects.
+    fn check_const_values(&self) {
+        if let Some(optional) = self.optionals.iter().find(|o| o.lacks_const_value()) {
+            panic!(
+                "optional '{}' takes an optional value but has no const_value",
+                optional.name()
+            );
+        }
+        for action in self.actions.iter() {
+            action.check_const_values();
+        }
+    }
+
+    /// Checks the constraints of every parser on the action path against the
+    /// complete results, so a sub-action may refer to a parent's arguments.
+    /// Errors carry the context of the parser declaring the constraint.
+    fn check_constraints(&self, result: &CmdParsingResults) -> Result<(), ParseError> {
+        let path = result.get_action_path();
+        let mut parsers = vec![self];
+        for action in path.iter().skip(1) {
+            let current = parsers.last().expect("root parser is on the path");
+            match current.actions.iter().find(|item| item.name == *action) {
+                Some(next) => parsers.push(next),
+                None => break,
             }
         }
-        Ok(remaining_cmd_line)
+        let describe = |name: &str| {
+            parsers
+                .iter()
+                .find(|parser| parser.switch_names(name).is_some())
+                .map_or(name.to_string(), |parser| parser.describe_switch(name))
+        };
+        for (depth, parser) in parsers.iter().enumerate() {
+            let Some(violated) = parser.constraints.iter().find(|c| !c.holds(result)) else {
+                continue;
+            };
+            let context = ErrorContext::new(&path[..=depth], parser.help());
+            return Err(match violated {
+                Constraint::Requires { argument, required } => ParseError::MissingDependency {
+                    argument: describe(argument),
+                    required: describe(required),
+                    context,
+                },
+                Constraint::ConflictsWith { argument, other } => ParseError::MutuallyExclusive {
+                    arguments: vec![describe(argument), describe(other)],
+                    context,
+                },
+                Constraint::RequiredUnless { argument, unless } => ParseError::MissingRequired {
+                    arguments: vec![format!("{} | {}", describe(argument), describe(unless))],
+                    context,
+                },
+            });
+        }
+        Ok(())
+    }
+
+    fn in_context(
+        &self,
+        error: ParseError,
+        result: &CmdParsingResults,
+        offset: usize,
+    ) -> ParseError {
+        error.in_context(offset, result.get_action_path(), self.help())
+    }
+
+    fn check_for_help(
+        &self,
+        result: &CmdParsingResults,
+        cmdline: &[String],
+    ) -> Result<(), ParseError> {
+        match cmdline.first().map(|token| token.as_str()) {
+            Some("--help") | Some("-h") => Err(ParseError::HelpRequested {
+                context: ErrorContext::new(result.get_action_path(), self.help()),
+            }),
+            _ => Ok(()),
+        }
     }
 
-    fn parse_positional_arguments<'b>(
+    fn parse_default_arguments<'b>(
         &self,
         result: &mut CmdParsingResults,
         cmdline: &'b [String],
-    ) -> Result<&'b [String], String> {
+        offset: usize,
+    ) -> Result<&'b [String], ParseError> {
         let mut remaining_cmd_line = cmdline;
-        for (_, item) in self.positionals.iter().enumerate() {
-            self.check_for_help(remaining_cmd_line)?;
-            match item.parse(result, remaining_cmd_line) {
-                Ok(remains) => remaining_cmd_line = remains,
-                Err(e) => return Err(format!("Bad Cmd Arguments: {} \n\n {}", e, self.help())),
-            }
+        for item in self.defaults.iter() {
+            remaining_cmd_line = item
+                .parse(result, remaining_cmd_line)
+                .map_err(|e| self.in_context(e, result, offset))?;
         }
         Ok(remaining_cmd_line)
     }
 
-    fn parse_optional_arguments<'b>(
+    /// Fills the positionals from the bare tokens collected while scanning,
+    /// `positions` holds the index of each token in the full command line.
+    fn parse_positional_arguments(
         &self,
         result: &mut CmdParsingResults,
-        cmdline: &'b [String],
-    ) -> Result<&'b [String], String> {
-        let mut remaining_cmd_line = cmdline;
-        // parse the command line content
-        for (_, item) in self.optionals.iter().enumerate() {
-            self.check_for_help(remaining_cmd_line)?;
-            match item.parse(result, remaining_cmd_line) {
-                Ok(remains) => remaining_cmd_line = remains,
-                Err(e) => return Err(format!("Bad Cmd Arguments: {} \n\n {}", e, self.help())),
-            }
+        tokens: &[String],
+        positions: &[usize],
+    ) -> Result<(), ParseError> {
+        let nargs: Vec<Nargs> = self.positionals.iter().map(|p| p.nargs()).collect();
+        let counts = nargs::distribute(&nargs, tokens.len()).map_err(|index| {
+            let missing = ParseError::MissingPositional {
+                argument: self.positionals[index].name().to_string(),
+                context: Box::default(),
+            };
+            self.in_context(missing, result, 0)
+        })?;
+        let mut start = 0;
+        for (item, count) in self.positionals.iter().zip(counts) {
+            item.parse(result, &tokens[start..start + count])
+                .map_err(|e| {
+                    // positions within the item's slice map to the collected tokens
+                    let shift = match e.position() {
+                        Some(index) => positions[start + index] - index,
+                        None => 0,
+                    };
+                    self.in_context(e, result, shift)
+                })?;
+            start += count;
         }
-        // add default args (if exist) for all not added optional arguments
-        for (_, item) in self.optionals.iter().enumerate() {
-            if !result.keys().contains(&&item.name()) {
-                match item.default() {
-                    Some(default) => result.add_result_value(item.name(), item.parser()(&default)),
-                    None => {}
-                }
-            }
+        Ok(())
+    }
+
+    /// Whether scanning stops at the bare `token`: the positionals cannot take
+    /// another one, or it is the full name of an action and they already hold
+    /// their minimum. Abbreviations only select an action where the positionals
+    /// cannot take the token.
+    fn positionals_filled(&self, collected: usize, token: &str) -> bool {
+        let capacity: Option<usize> = self.positionals.iter().map(|p| p.nargs().max()).sum();
+        if capacity.is_some_and(|capacity| collected >= capacity) {
+            return true;
         }
-        Ok(remaining_cmd_line)
+        let minimum: usize = self.positionals.iter().map(|p| p.nargs().min()).sum();
+        collected >= minimum && self.exact_action(token).is_some()
     }
 
-    fn parse_flag_arguments<'b>(
+    fn parse_optional_arguments<'b>(
         &self,
         result: &mut CmdParsingResults,
         cmdline: &'b [String],
-    ) -> Result<&'b [String], String> {
-        let mut remaining_cmd_line = cmdline;
-        // parse the command line content
-        for (_, item) in self.flags.iter().enumerate() {
-            self.check_for_help(remaining_cmd_line)?;
-            match item.parse(result, remaining_cmd_line) {
-                Ok(remains) => remaining_cmd_line = remains,
-                Err(e) => return Err(format!("Bad Cmd Arguments: {} \n\n {}", e, self.help())),
-            }
-        }
-        // add false for all not added flags
-        for (_, item) in self.flags.iter().enumerate() {
-            if !result.keys().contains(&&item.name().to_string()) {
-                result.add_result_value(item.name().to_string(), Box::new(false))
+        offset: usize,
+    ) -> Result<&'b [String], ParseError> {
+        // try every optional against the switch at the front of the command line
+        for item in self.optionals.iter() {
+            let remains = item
+                .parse(result, cmdline)
+                .map_err(|e| self.in_context(e, result, offset))?;
+            if remains.len() != cmdline.len() {
+                return Ok(remains);
             }
         }
-        Ok(remaining_cmd_line)
+        Ok(cmdline)
     }
 
-    fn find_matching_action(&self, name: &str) -> Option<&Parser<F>> {
-        self.actions.iter().find(|action| action.name == name)
+    /// The short and long name of the optional or flag called `name`.
+    fn switch_names(&self, name: &str) -> Option<(char, &str)> {
+        let optional = self
+            .optionals
+            .iter()
+            .find(|optional| optional.name() == name)
+            .map(|optional| (optional.short(), optional.long()));
+        let flag = self
+            .flags
+            .iter()
+            .find(|flag| flag.name() == name)
+            .map(|flag| (flag.short(), flag.long()));
+        optional.or(flag)
     }
 
-    fn parse_action_arguments<'b>(
-        &self,
-        result: &mut CmdParsingResults,
-        cmdline: &'b [String],
-    ) -> Result<(&'b [String], Box<F>), String> {
-        self.check_for_help(cmdline)?;
-        if self.actions.is_empty() {
-            let main = self
-                .main
-                .borrow_mut()
-                .take()
-                .unwrap_or_else(|| panic!("leaf parser '{}' has no main function", self.name));
-            return Ok((cmdline, main));
-        }
-        let action_name = cmdline
-            .first()
-            .ok_or_else(|| format!("You have to chose an action. \n\n {}", self.help()))?;
-        let action = self
-            .find_matching_action(action_name)
-            .ok_or_else(|| format!("Unknown action {}", action_name))?;
-        let (remaining, main) = action.parse_tree(result, &cmdline[1..])?;
-        if remaining.is_empty() {
-            Ok((remaining, main))
-        } else {
-            Err(format!(
-                "Too many supplied arguments after: {:?}\n\n{}",
-                remaining,
-                self.help()
-            ))
-        }
-    }
-
-    fn parse_tree<'b>(
-        &self,
-        result: &mut CmdParsingResults,
-        cmdline: &'b [String],
-    ) -> Result<(&'b [String], Box<F>), String> {
-        result.set_action(self.name.clone());
-        let mut remaining_cmd_line: &[String] = cmdline;
-        remaining_cmd_line = self.parse_default_arguments(result, remaining_cmd_line)?;
-        remaining_cmd_line = self.parse_positional_arguments(result, remaining_cmd_line)?;
-        remaining_cmd_line = self.parse_optional_arguments(result, remaining_cmd_line)?;
-        remaining_cmd_line = self.parse_flag_arguments(result, remaining_cmd_line)?;
-        self.parse_action_arguments(result, remaining_cmd_line)
+    /// The `-s/--long` switches of the optional or flag called `name`.
+    fn describe_switch(&self, name: &str) -> String {
+        match self.switch_names(name) {
+            Some((short, long)) => format!("-{}/--{}", short, long),
+            None => name.to_string(),
+        }
     }
 
-    fn build_usage_line(&self) -> String {
-        let mut usage = "usage: ".to_string() + self.name.as_str();
-        for positional in self.positionals.iter() {
-            usage += &format!(" [{}]", positional.name());
+    /// Checks the optionals and flags that were given, at most one per
+    /// exclusive group and every required optional or group, before their
+    /// defaults are added. Each layer only fills what the ones before it left
+    /// missing, so the command line wins over the environment, which wins over
+    /// config files, which win over the defaults.
+    /// Drops the exclusive group members set by a lower layer than another
+    /// member, so that e.g. a switch on the command line overrides a member
+    /// set in a config file instead of conflicting with it.
+    fn drop_overridden_members(&self, result: &mut CmdParsingResults) {
+        for group in self.exclusive_groups.iter() {
+            for member in group.overridden(result) {
+                result.remove_value(member);
+            }
         }
-        if !self.optionals.is_empty() || !self.flags.is_empty() {
-            usage += " {";
-            let shorts: Vec<String> = self
-                .optionals
-                .iter()
-                .map(|o| format!("-{}", o.short()))
-                .chain(self.flags.iter().map(|f| format!("-{}", f.short())))
-                .collect();
-            usage += &shorts.join(",");
-            usage += "}";
+    }
+
+    fn check_given_arguments(&self, result: &CmdParsingResults) -> Result<(), ParseError> {
+        for group in self.exclusive_groups.iter() {
+            let given = group.given(result);
+            if given.len() > 1 {
+                return Err(ParseError::MutuallyExclusive {
+                    arguments: given
+                        .iter()
+                        .map(|name| self.describe_switch(name))
+                        .collect(),
+                    context: ErrorContext::new(result.get_action_path(), self.help()),
+                });
+            }
         }
-        for (num, action) in self.actions.iter().enumerate() {
-            if num != 0 {
-                usage += ",";
-            } else {
-                usage += " ";
+        let mut missing: Vec<String> = self
+            .optionals
+            .iter()
+            .filter(|item| item.is_required() && !result.keys().contains(&&item.name()))
+            .map(|item| self.describe_switch(&item.name()))
+            .collect();
+        for group in self.exclusive_groups.iter() {
+            if group.is_required() && group.given(result).is_empty() {
+                let members: Vec<String> = group
+                    .members()
+                    .iter()
+                    .map(|name| self.describe_switch(name))
+                    .collect();
+                missing.push(members.join(" | "));
             }
-            usage += action.name.as_str();
         }
-        usage.trim().to_string()
+        if missing.is_empty() {
+            return Ok(());
+        }
+        Err(ParseError::MissingRequired {
+            arguments: missing,
+            context: ErrorContext::new(result.get_action_path(), self.help()),
+        })
     }
 
-    fn build_help_body(&self) -> String {
-        let mut body = String::new();
-        for positional in self.positionals.iter() {
-            body += &positional.help();
-            body += "\n";
+    /// Fills the optionals and flags not given on the command line from their
+    /// environment variables, if set, before anything checks what was given.
+    fn add_environment_values(
+        &self,
+        result: &mut CmdParsingResults,
+        lookup: EnvLookup,
+    ) -> Result<(), ParseError> {
+        for item in self.optionals.iter() {
+            if !result.keys().contains(&&item.name()) {
+                item.store_environment(result, lookup)
+                    .map_err(|e| self.in_context(e, result, 0))?;
+            }
         }
-        for optional in self.optionals.iter() {
-            body += &optional.help();
-            body += "\n";
+        for item in self.flags.iter() {
+            if !result.keys().contains(&&item.name().to_string()) {
+                item.store_environment(result, lookup)
+                    .map_err(|e| self.in_context(e, result, 0))?;
+            }
         }
-        for flag in self.flags.iter() {
-            body += &flag.help();
-            body += "\n";
+        Ok(())
+    }
+
+    /// Loads the files given with [`Parser::with_config_file`] that exist,
+    /// then those named by the config file optionals that were given.
+    fn load_config_files(&self, result: &mut CmdParsingResults) -> Result<(), ParseError> {
+        let mut paths: Vec<String> = self
+            .config_files
+            .iter()
+            .filter(|path| Path::new(path).exists())
+            .cloned()
+            .collect();
+        for item in self.optionals.iter().filter(|item| item.is_config_file()) {
+            let name = item.name();
+            let path = match result.get_optional_value::<String>(&name) {
+                Some(path) => Some(path.clone()),
+                None => result
+                    .get_optional_value::<PathBuf>(&name)
+                    .map(|path| path.to_string_lossy().into_owned()),
+            };
+            paths.extend(path);
         }
-        for action in self.actions.iter() {
-            let name = action.name.as_str();
-            let spaces = 22 - name.len();
-            body += name;
-            body += &String::from_utf8(vec![b' '; spaces])
-                .expect("should be a string of whitespaces");
-            body += action.doc.as_str();
-            body += "\n";
+        for path in paths {
+            let file = ConfigFile::load(&path).map_err(|e| self.in_context(e, result, 0))?;
+            result.add_config_file(file);
         }
-        body
+        Ok(())
     }
 
-    pub fn help(&self) -> String {
-        let header = self.name.clone() + " - " + self.doc.as_str();
-        header + "\n\n" + &self.build_usage_line() + "\n\n" + &self.build_help_body()
-    }
+    /// Fills the optionals and flags still missing from the config files'
+    /// section for this parser, rejecting keys and sections that name none of
+    /// its optionals, flags or actions.
+    fn add_config_values(&self, result: &mut CmdParsingResults) -> Result<(), ParseError> {
+        let section = result.get_action_path()[1..].to_vec();
+        for file in result.config_files() {
+            for (key, line) in file.keys(&section) {
+                if !self.knows_config_key(key) {
+                    let suggestion = suggestion::suggest(
+                        key,
+                        self.optionals
+                            .iter()
+                            .map(|item| item.long())
+                            .chain(self.flags.iter().map(|item| item.long())),
+                    );
+                    let reason = format!(
+                        "unknown option '{}'{}",
+                        key,
+                        suggestion.map_or(String::new(), |s| format!(", did you mean {}?", s))
+                    );
+                    let error = config_file::failure(file.path(), Some(line), reason);
+                    return Err(self.in_context(error, result, 0));
+                }
+            }
+            if let Some(unknown) = file
+                .subsections(&section)
+                .find(|name| !self.actions.iter().any(|action| action.name == *name))
+            {
+                let reason = format!("unknown action section [{}]", unknown);
+                let error = config_file::failure(file.path(), None, reason);
+                return Err(self.in_context(error, result, 0));
+            }
+        }
+        for item in self.optionals.iter() {
+            let name = item.name();
+            if item.is_config_file() || result.keys().contains(&&name) {
+                continue;
+            }
+            if let Some(value) = result.config_value(&section, &[item.long(), &name]) {
+                item.store_config(result, &value)
+                    .map_err(|e| self.in_context(e, result, 0))?;
+            }
+        }
+        for item in self.flags.iter() {
+            if result.keys().contains(&&item.name().to_string()) {
+                continue;
+            }
+            if let Some(value) = result.config_value(&section, &[item.long(), item.name()]) {
+                item.store_config(result, &value)
+                    .map_err(|e| self.in_context(e, result, 0))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether a config file key names one of this parser's optionals or
+    /// flags, by long name or argument name.
+    fn knows_config_key(&self, key: &str) -> bool {
+        self.optionals
+            .iter()
+            .any(|item| item.long() == key || item.name() == key)
+            || self
+                .flags
+                .iter()
+                .any(|item| item.long() == key || item.name() == key)
+    }
+
+    fn add_optional_defaults(&self, result: &mut CmdParsingResults) -> Result<(), ParseError> {
+        // add default args (if exist) for all not added optional arguments
+        for item in self.optionals.iter() {
+            if result.keys().contains(&&item.name()) {
+                continue;
+            }
+            item.store_default(result)
+                .map_err(|e| self.in_context(e, result, 0))?;
+        }
+        Ok(())
+    }
+
+    /// Consumes the switch at the front of `cmdline`, classified as `switch`,
+    /// with an optional or flag of this parser, failing if none matches.
+    fn parse_switch<'b>(
+        &self,
+        result: &mut CmdParsingResults,
+        cmdline: &'b [String],
+        switch: Token,
+        offset: usize,
+    ) -> Result<&'b [String], ParseError> {
+        if let Token::Short { name, rest } = switch
+            && !rest.is_empty()
+            && self.flags.iter().any(|flag| flag.short() == name)
+        {
+            return self.parse_short_cluster(result, cmdline, offset);
+        }
+        let remains = self.parse_optional_arguments(result, cmdline, offset)?;
+        let remains = self.parse_flag_arguments(result, remains, offset)?;
+        if remains.len() == cmdline.len()
+            && self.allow_abbrev
+            && let Token::Long { name, value } = switch
+        {
+            return self.parse_abbreviation(result, cmdline, name, value, offset);
+        }
+        if remains.len() == cmdline.len() {
+            return Err(ParseError::UnknownOption {
+                option: cmdline[0].clone(),
+                suggestion: self.suggest_option(switch),
+                position: offset,
+                context: ErrorContext::new(result.get_action_path(), self.help()),
+            });
+        }
+        Ok(remains)
+    }
+
+    /// Expands the abbreviated long switch `--name[=value]` at the front of
+    /// `cmdline` to the one long name it is a prefix of and parses that.
+    fn parse_abbreviation<'b>(
+        &self,
+        result: &mut CmdParsingResults,
+        cmdline: &'b [String],
+        name: &str,
+        value: Option<&str>,
+        offset: usize,
+    ) -> Result<&'b [String], ParseError> {
+        let candidates: Vec<&str> = self
+            .optionals
+            .iter()
+            .map(|optional| optional.long())
+            .chain(self.flags.iter().map(|flag| flag.long()))
+            .filter(|long| !name.is_empty() && long.starts_with(name))
+            .collect();
+        let long = match candidates[..] {
+            [long] => long,
+            [] => {
+                return Err(ParseError::UnknownOption {
+                    option: cmdline[0].clone(),
+                    suggestion: self.suggest_option(Token::classify(&cmdline[0])),
+                    position: offset,
+                    context: ErrorContext::new(result.get_action_path(), self.help()),
+                });
+            }
+            _ => {
+                return Err(ParseError::AmbiguousOption {
+                    option: cmdline[0].clone(),
+                    candidates: candidates
+                        .iter()
+                        .map(|long| format!("--{}", long))
+                        .collect(),
+                    position: offset,
+                    context: ErrorContext::new(result.get_action_path(), self.help()),
+                });
+            }
+        };
+        let mut expanded = vec![match value {
+            Some(value) => format!("--{}={}", long, value),
+            None => format!("--{}", long),
+        }];
+        expanded.extend_from_slice(&cmdline[1..]);
+        let remains = self.parse_optional_arguments(result, &expanded, offset)?;
+        let remains = self.parse_flag_arguments(result, remains, offset)?;
+        Ok(&cmdline[cmdline.len() - remains.len()..])
+    }
+
+    /// The long switch a mistyped `--name` was probably meant to be.
+    fn suggest_option(&self, switch: Token) -> Option<String> {
+        match switch {
+            Token::Long { name, .. } => suggestion::suggest(
+                name,
+                self.optionals
+                    .iter()
+                    .map(|optional| optional.long())
+                    .chain(self.flags.iter().map(|flag| flag.long())),
+            )
+            .map(|long| format!("--{}", long)),
+            _ => None,
+        }
+    }
+
+    /// Whether a short name is a digit, which makes tokens like `-1` switches
+    /// instead of negative numbers, as in argparse.
+    fn has_digit_shorts(&self) -> bool {
+        self.optionals
+            .iter()
+            .map(|optional| optional.short())
+            .chain(self.flags.iter().map(|flag| flag.short()))
+            .any(|short| short.is_ascii_digit())
+    }
+
+    /// Splits a cluster of short switches like `-xvf` at the front of
+    /// `cmdline`: every char names a flag, except that an optional takes the
+    /// rest of the token, or the tokens after it, as its value.
+    fn parse_short_cluster<'b>(
+        &self,
+        result: &mut CmdParsingResults,
+        cmdline: &'b [String],
+        offset: usize,
+    ) -> Result<&'b [String], ParseError> {
+        let cluster = &cmdline[0];
+        for (index, short) in cluster.char_indices().skip(1) {
+            if let Some(flag) = self.flags.iter().find(|flag| flag.short() == short) {
+                flag.store(result);
+                continue;
+            }
+            if let Some(optional) = self.optionals.iter().find(|o| o.short() == short) {
+                let mut rest = vec![format!("-{}", &cluster[index..])];
+                rest.extend_from_slice(&cmdline[1..]);
+                let remains = optional
+                    .parse(result, &rest)
+                    .map_err(|e| self.in_context(e, result, offset))?;
+                return Ok(&cmdline[cmdline.len() - remains.len()..]);
+            }
+            return Err(ParseError::UnknownOption {
+                option: format!("-{}", short),
+                suggestion: None,
+                position: offset,
+                context: ErrorContext::new(result.get_action_path(), self.help()),
+            });
+        }
+        Ok(&cmdline[1..])
+    }
+
+    fn parse_flag_arguments<'b>(
+        &self,
+        result: &mut CmdParsingResults,
+        cmdline: &'b [String],
+        offset: usize,
+    ) -> Result<&'b [String], ParseError> {
+        // try every flag against the switch at the front of the command line
+        for item in self.flags.iter() {
+            let remains = item
+                .parse(result, cmdline)
+                .map_err(|e| self.in_context(e, result, offset))?;
+            if remains.len() != cmdline.len() {
+                return Ok(remains);
+            }
+        }
+        Ok(cmdline)
+    }
+
+    fn add_flag_defaults(&self, result: &mut CmdParsingResults) {
+        // add false (or a count of 0) for all not added flags
+        for item in self.flags.iter() {
+            if result.keys().contains(&&item.name().to_string()) {
+                continue;
+            }
+            if item.counts() {
+                result.add_typed_result_value(
+                    item.name().to_string(),
+                    Box::new(0_u8),
+                    Some(type_name::<u8>()),
+                )
+            } else {
+                result.add_typed_result_value(
+                    item.name().to_string(),
+                    Box::new(false),
+                    Some(type_name::<bool>()),
+                )
+            }
+            result.set_value_source(item.name(), ValueSource::Default);
+        }
+    }
+
+    /// The name followed by the aliases of this parser.
+    fn names(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(self.name.as_str()).chain(self.aliases.iter().map(|a| a.as_str()))
+    }
+
+    /// The action called `name` by its name or one of its aliases.
+    fn exact_action(&self, name: &str) -> Option<&Parser<F>> {
+        self.actions
+            .iter()
+            .find(|action| action.names().any(|known| known == name))
+    }
+
+    fn find_matching_action(&self, name: &str) -> Option<&Parser<F>> {
+        let exact = self.exact_action(name);
+        match self.abbreviated_actions(name)[..] {
+            [action] if exact.is_none() => Some(action),
+            _ => exact,
+        }
+    }
+
+    /// The actions `name` is a prefix of a name or alias of, if abbreviations
+    /// are allowed.
+    fn abbreviated_actions(&self, name: &str) -> Vec<&Parser<F>> {
+        if !self.allow_action_abbrev || name.is_empty() {
+            return Vec::new();
+        }
+        self.actions
+            .iter()
+            .filter(|action| action.names().any(|known| known.starts_with(name)))
+            .collect()
+    }
+
+    fn parse_action_arguments(
+        &self,
+        result: &mut CmdParsingResults,
+        cmdline: &[String],
+        offset: usize,
+        options_ended: bool,
+        env_lookup: EnvLookup,
+    ) -> Result<Option<Box<F>>, ParseError> {
+        if !options_ended {
+            self.check_for_help(result, cmdline)?;
+        }
+        if self.actions.is_empty() {
+            if !cmdline.is_empty() {
+                return Err(ParseError::UnexpectedArguments {
+                    arguments: cmdline.to_vec(),
+                    position: offset,
+                    context: ErrorContext::new(result.get_action_path(), self.help()),
+                });
+            }
+            let main = self.main.borrow_mut().take();
+            if main.is_none() && !self.inherit_main {
+                panic!("leaf parser '{}' has no main function", self.name);
+            }
+            return Ok(main);
+        }
+        let action_name = cmdline.first().ok_or_else(|| ParseError::MissingAction {
+            position: offset,
+            context: ErrorContext::new(result.get_action_path(), self.help()),
+        })?;
+        let action = match self.find_matching_action(action_name) {
+            Some(action) => action,
+            None => return Err(self.unmatched_action(result, action_name, offset)),
+        };
+        // a leaf inheriting its main runs the closest ancestor's main
+        let main =
+            action.parse_tree(result, &cmdline[1..], offset + 1, options_ended, env_lookup)?;
+        Ok(main.or_else(|| self.main.borrow_mut().take()))
+    }
+
+    /// The error for an `action_name` that selects no single action.
+    fn unmatched_action(
+        &self,
+        result: &CmdParsingResults,
+        action_name: &str,
+        offset: usize,
+    ) -> ParseError {
+        let candidates = self.abbreviated_actions(action_name);
+        if candidates.len() > 1 {
+            return ParseError::AmbiguousAction {
+                action: action_name.to_string(),
+                candidates: candidates
+                    .iter()
+                    .map(|action| action.name.clone())
+                    .collect(),
+                position: offset,
+                context: ErrorContext::new(result.get_action_path(), self.help()),
+            };
+        }
+        ParseError::UnknownAction {
+            action: action_name.to_string(),
+            suggestion: suggestion::suggest(
+                action_name,
+                self.actions.iter().flat_map(|action| action.names()),
+            ),
+            position: offset,
+            context: ErrorContext::new(result.get_action_path(), self.help()),
+        }
+    }
+
+    /// Parses `cmdline`, whose first token sits at index `offset` of the full
+    /// command line, and returns the main of the matched leaf action, or for
+    /// a leaf with [`Parser::inherit_main`], of the closest parser on the way
+    /// to it that has one. Once `options_ended` by `--`, every token is
+    /// treated as a bare value. Environment variables are read through
+    /// `env_lookup`.
+    fn parse_tree(
+        &self,
+        result: &mut CmdParsingResults,
+        cmdline: &[String],
+        offset: usize,
+        mut options_ended: bool,
+        env_lookup: EnvLookup,
+    ) -> Result<Option<Box<F>>, ParseError> {
+        result.enter_action(self.name.clone());
+        let mut remaining_cmd_line: &[String] = cmdline;
+        remaining_cmd_line = self.parse_default_arguments(result, remaining_cmd_line, offset)?;
+        // scan the command line: switches may appear anywhere, bare tokens are
+        // collected for the positionals until they are filled
+        let mut bare_tokens: Vec<String> = Vec::new();
+        let mut bare_positions: Vec<usize> = Vec::new();
+        while let Some(token) = remaining_cmd_line.first() {
+            let position = offset + cmdline.len() - remaining_cmd_line.len();
+            if !options_ended {
+                self.check_for_help(result, remaining_cmd_line)?;
+                match Token::classify(token) {
+                    Token::Separator => {
+                        options_ended = true;
+                        remaining_cmd_line = &remaining_cmd_line[1..];
+                        continue;
+                    }
+                    Token::Value(_) => {}
+                    Token::NegativeNumber(_) if !self.has_digit_shorts() => {}
+                    switch => {
+                        remaining_cmd_line =
+                            self.parse_switch(result, remaining_cmd_line, switch, position)?;
+                        continue;
+                    }
+                }
+            }
+            if self.positionals_filled(bare_tokens.len(), token) {
+                break;
+            }
+            bare_tokens.push(token.clone());
+            bare_positions.push(position);
+            remaining_cmd_line = &remaining_cmd_line[1..];
+        }
+        self.parse_positional_arguments(result, &bare_tokens, &bare_positions)?;
+        self.add_environment_values(result, env_lookup)?;
+        self.load_config_files(result)?;
+        self.add_config_values(result)?;
+        self.drop_overridden_members(result);
+        self.check_given_arguments(result)?;
+        self.add_optional_defaults(result)?;
+        self.add_flag_defaults(result);
+        let position = offset + cmdline.len() - remaining_cmd_line.len();
+        self.parse_action_arguments(
+            result,
+            remaining_cmd_line,
+            position,
+            options_ended,
+            env_lookup,
+        )
+    }
+
+    fn build_usage_line(&self) -> String {
+        let mut usage = "usage: ".to_string() + self.name.as_str();
+        for positional in self.positionals.iter() {
+            usage += &format!(" [{}]", positional.display_name());
+        }
+        let grouped = |name: &str| self.exclusive_groups.iter().any(|g| g.contains(name));
+        let shorts: Vec<String> = self
+            .optionals
+            .iter()
+            .filter(|o| !grouped(&o.name()) && !o.choices().is_restricted())
+            .map(|o| format!("-{}", o.short()))
+            .chain(
+                self.flags
+                    .iter()
+                    .filter(|f| !grouped(f.name()))
+                    .map(|f| format!("-{}", f.short())),
+            )
+            .collect();
+        if !shorts.is_empty() {
+            usage += " {";
+            usage += &shorts.join(",");
+            usage += "}";
+        }
+        for optional in self.optionals.iter() {
+            if grouped(&optional.name()) || !optional.choices().is_restricted() {
+                continue;
+            }
+            let switch = format!("-{}{}", optional.short(), optional.choices().suffix());
+            if optional.is_required() {
+                usage += &format!(" ({})", switch);
+            } else {
+                usage += &format!(" [{}]", switch);
+            }
+        }
+        for group in self.exclusive_groups.iter() {
+            let longs: Vec<String> = group
+                .members()
+                .iter()
+                .filter_map(|name| self.switch_names(name))
+                .map(|(_, long)| format!("--{}", long))
+                .collect();
+            if group.is_required() {
+                usage += &format!(" ({})", longs.join(" | "));
+            } else {
+                usage += &format!(" [{}]", longs.join(" | "));
+            }
+        }
+        for (num, action) in self.actions.iter().enumerate() {
+            if num != 0 {
+                usage += ",";
+            } else {
+                usage += " ";
+            }
+            usage += &action.names().collect::<Vec<&str>>().join("|");
+        }
+        usage.trim().to_string()
+    }
+
+    /// The help lines of the positionals, optionals and flags for which
+    /// `include` holds, in that order.
+    fn argument_help_lines(&self, include: impl Fn(&str) -> bool) -> Vec<String> {
+        let positionals = self
+            .positionals
+            .iter()
+            .filter(|positional| include(positional.name()))
+            .map(|positional| positional.help());
+        let optionals = self
+            .optionals
+            .iter()
+            .filter(|optional| include(&optional.name()))
+            .map(|optional| optional.help());
+        let flags = self
+            .flags
+            .iter()
+            .filter(|flag| include(flag.name()))
+            .map(|flag| flag.help());
+        positionals.chain(optionals).chain(flags).collect()
+    }
+
+    /// The blocks of the help body: the arguments outside of any argument
+    /// group followed by the actions, then one titled block per group.
+    fn help_sections(&self) -> Vec<HelpSection<'_>> {
+        let mut lines = self.argument_help_lines(|name| {
+            !self
+                .argument_groups
+                .iter()
+                .any(|group| group.contains(name))
+        });
+        for action in self.actions.iter() {
+            let name = action.names().collect::<Vec<&str>>().join(",");
+            let spaces = 22_usize.saturating_sub(name.len()).max(1);
+            lines.push(name + &" ".repeat(spaces) + action.doc.as_str());
+        }
+        let mut sections = vec![HelpSection {
+            title: None,
+            description: "",
+            lines,
+        }];
+        for group in self.argument_groups.iter() {
+            sections.push(HelpSection {
+                title: Some(group.title()),
+                description: group.description(),
+                lines: self.argument_help_lines(|name| group.contains(name)),
+            });
+        }
+        sections
+    }
+
+    fn build_help_body(&self) -> String {
+        let mut body = String::new();
+        for section in self.help_sections() {
+            if let Some(title) = section.title {
+                body += "\n";
+                body += title;
+                body += ":\n";
+                if !section.description.is_empty() {
+                    body += section.description;
+                    body += "\n";
+                }
+            }
+            for line in section.lines {
+                body += &line;
+                body += "\n";
+            }
+        }
+        body
+    }
+
+    pub fn help(&self) -> String {
+        let header = self.name.clone() + " - " + self.doc.as_str();
+        header + "\n\n" + &self.build_usage_line() + "\n\n" + &self.build_help_body()
+    }
+
+    /// Writes a tab completion script for `shell` to `out`, offering the
+    /// sub-actions with their aliases, the switches and the choices of every
+    /// level of the parser tree. Source it from the shell's startup file.
+    pub fn generate_completion(&self, shell: Shell, out: &mut impl Write) -> io::Result<()> {
+        let command = self.completion_command();
+        let script = match shell {
+            Shell::Bash => completion::bash(&command),
+            Shell::Zsh => completion::zsh(&command),
+            Shell::Fish => completion::fish(&command),
+        };
+        out.write_all(script.as_bytes())
+    }
+
+    fn completion_command(&self) -> Command {
+        let optionals = self.optionals.iter().map(|item| Switch {
+            short: item.short(),
+            long: item.long().to_string(),
+            doc: item.doc().to_string(),
+            takes_value: true,
+            choices: item.choices().values().to_vec(),
+        });
+        let flags = self.flags.iter().map(|item| Switch {
+            short: item.short(),
+            long: item.long().to_string(),
+            doc: item.doc().to_string(),
+            takes_value: false,
+            choices: Vec::new(),
+        });
+        let values = self.positionals.iter().flat_map(|item| {
+            item.choices().values().iter().map(|value| Candidate {
+                word: value.clone(),
+                doc: item.doc().to_string(),
+            })
+        });
+        Command {
+            names: self.names().map(|name| name.to_string()).collect(),
+            doc: self.doc.clone(),
+            switches: optionals.chain(flags).collect(),
+            values: values.collect(),
+            actions: self
+                .actions
+                .iter()
+                .map(|action| action.completion_command())
+                .collect(),
+        }
+    }
 }
 
-#[cfg(test)]
-mod test {
-    use super::*;
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_dir::TestDir;
+
+    type StubAction = dyn FnOnce() -> Result<(), String>;
+
+    fn stub_main() -> Result<(), String> {
+        Ok(())
+    }
+
+    fn get_basic_cmd_parser() -> Parser<StubAction> {
+        Parser::new("test", "I am a test")
+            .add_default("default".to_string(), "test".to_string())
+            .add_positional("positional", "I am the positional")
+            .add_optional(
+                "optional",
+                "optional",
+                'o',
+                Some("default"),
+                "I am the optional",
+            )
+            .add_flag("flag", "flag", 'f', "I am the flag")
+            .with_main(stub_main)
+    }
+
+    fn get_nested_parser() -> Parser<StubAction> {
+        get_basic_cmd_parser().add_action(
+            Parser::new("compute", "I am da computaaah")
+                .add_positional("stuff", "stuff indeed")
+                .with_main(stub_main),
+        )
+    }
+
+    #[test]
+    fn parse_command_line_wrong_action() {
+        let args: &[String] = &[
+            "positional".to_string(),
+            "-o".to_string(),
+            "optional".to_string(),
+            "-f".to_string(),
+            "wrong-action".to_string(),
+        ];
+        let parser: Parser<StubAction> = get_nested_parser();
+        match parser.parse(Vec::from(args)) {
+            Err(ParseError::UnknownAction {
+                action, position, ..
+            }) => {
+                assert_eq!(action, "wrong-action");
+                assert_eq!(position, 4);
+            }
+            Err(e) => panic!("unexpected error {:?}", e),
+            Ok(_) => panic!("Should not have parsed"),
+        }
+    }
+
+    #[test]
+    fn parse_command_line_missing_action() {
+        let args: &[String] = &[
+            "positional".to_string(),
+            "-o".to_string(),
+            "optional".to_string(),
+            "-f".to_string(),
+        ];
+        let parser: Parser<StubAction> = get_nested_parser();
+        assert!(matches!(
+            parser.parse(Vec::from(args)),
+            Err(ParseError::MissingAction { position: 4, .. })
+        ))
+    }
+
+    #[test]
+    fn parse_command_line_full_action() {
+        let args: &[String] = &[
+            "positional".to_string(),
+            "-o".to_string(),
+            "optional".to_string(),
+            "-f".to_string(),
+        ];
+        let parser: Parser<StubAction> = get_basic_cmd_parser();
+        match parser.parse(Vec::from(args)) {
+            Ok((result, _main)) => {
+                assert_eq!(result.get_action(), "test");
+                assert_eq!(result.get_value::<String>("positional"), "positional");
+                assert_eq!(result.get_value::<String>("optional"), "optional");
+                assert!(result.get_flag("flag"));
+            }
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    #[test]
+    fn parse_command_line_full_sub_action() {
+        let args: &[String] = &[
+            "positional".to_string(),
+            "-o".to_string(),
+            "optional".to_string(),
+            "-f".to_string(),
+            "compute".to_string(),
+            "values".to_string(),
+        ];
+        let parser: Parser<StubAction> = get_nested_parser();
+        match parser.parse(Vec::from(args)) {
+            Ok((result, _main)) => {
+                assert_eq!(result.get_action(), "compute");
+                assert_eq!(result.get_value::<String>("positional"), "positional");
+                assert_eq!(result.get_value::<String>("optional"), "optional");
+                assert_eq!(result.get_value::<String>("stuff"), "values");
+                assert!(result.get_flag("flag"));
+            }
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    #[test]
+    fn parse_command_line_switches_in_any_order() {
+        let args: &[String] = &[
+            "-f".to_string(),
+            "-o".to_string(),
+            "optional".to_string(),
+            "positional".to_string(),
+        ];
+        let parser: Parser<StubAction> = get_basic_cmd_parser();
+        match parser.parse(Vec::from(args)) {
+            Ok((result, _main)) => {
+                assert_eq!(result.get_value::<String>("positional"), "positional");
+                assert_eq!(result.get_value::<String>("optional"), "optional");
+                assert!(result.get_flag("flag"));
+            }
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    #[test]
+    fn parse_command_line_switches_between_positionals() {
+        let args: &[String] = &[
+            "first".to_string(),
+            "--flag".to_string(),
+            "second".to_string(),
+            "-o".to_string(),
+            "optional".to_string(),
+        ];
+        let parser: Parser<StubAction> = Parser::new("test", "doc")
+            .add_positional("first", "first value")
+            .add_positional("second", "second value")
+            .add_optional("optional", "optional", 'o', None, "an optional")
+            .add_flag("flag", "flag", 'f', "a flag")
+            .with_main(stub_main);
+        let (result, _main) = parser.parse(Vec::from(args)).unwrap();
+        assert_eq!(result.get_value::<String>("first"), "first");
+        assert_eq!(result.get_value::<String>("second"), "second");
+        assert_eq!(result.get_value::<String>("optional"), "optional");
+        assert!(result.get_flag("flag"));
+    }
+
+    #[test]
+    fn parse_command_line_switches_before_sub_action() {
+        let args: &[String] = &[
+            "-f".to_string(),
+            "positional".to_string(),
+            "compute".to_string(),
+            "values".to_string(),
+        ];
+        let parser: Parser<StubAction> = get_nested_parser();
+        let (result, _main) = parser.parse(Vec::from(args)).unwrap();
+        assert_eq!(result.get_action(), "compute");
+        assert_eq!(result.get_value::<String>("positional"), "positional");
+        assert_eq!(result.get_value::<String>("optional"), "default");
+        assert!(result.get_flag("flag"));
+    }
+
+    #[test]
+    fn parse_command_line_too_many_positionals() {
+        let args: &[String] = &["positional".to_string(), "extra".to_string()];
+        let parser: Parser<StubAction> = get_basic_cmd_parser();
+        match parser.parse(Vec::from(args)) {
+            Err(ParseError::UnexpectedArguments {
+                arguments,
+                position,
+                ..
+            }) => {
+                assert_eq!(arguments, ["extra".to_string()]);
+                assert_eq!(position, 1);
+            }
+            Err(e) => panic!("unexpected error {:?}", e),
+            Ok(_) => panic!("Should not have parsed"),
+        }
+    }
+
+    #[test]
+    fn parse_command_line_missing_positional() {
+        let args: &[String] = &["-f".to_string()];
+        let parser: Parser<StubAction> = get_basic_cmd_parser();
+        match parser.parse(Vec::from(args)) {
+            Err(e) => {
+                assert!(matches!(e, ParseError::MissingPositional { .. }));
+                assert_eq!(e.argument(), Some("positional"));
+                assert_eq!(e.action_path(), ["test".to_string()]);
+            }
+            Ok(_) => panic!("Should not have parsed"),
+        }
+    }
+
+    #[test]
+    fn parse_command_line_missing_value_in_sub_action() {
+        let args: &[String] = &[
+            "positional".to_string(),
+            "compute".to_string(),
+            "values".to_string(),
+            "-o".to_string(),
+        ];
+        let parser: Parser<StubAction> = get_basic_cmd_parser().add_action(
+            Parser::new("compute", "I am da computaaah")
+                .add_positional("stuff", "stuff indeed")
+                .add_optional("other", "other", 'o', None, "another optional")
+                .with_main(stub_main),
+        );
+        match parser.parse(Vec::from(args)) {
+            Err(ParseError::MissingValue {
+                argument,
+                position,
+                context,
+            }) => {
+                assert_eq!(argument, "other");
+                assert_eq!(position, 3);
+                assert_eq!(
+                    context.action_path,
+                    ["test".to_string(), "compute".to_string()]
+                );
+            }
+            Err(e) => panic!("unexpected error {:?}", e),
+            Ok(_) => panic!("Should not have parsed"),
+        }
+    }
+
+    #[test]
+    fn parse_command_line_bad_conversion_is_an_error() {
+        let args: &[String] = &["-f".to_string(), "three".to_string()];
+        let parser: Parser<StubAction> = Parser::new("test", "doc")
+            .add_parsed_positional(
+                "count",
+                |val| Ok(Box::new(val.parse::<i32>().map_err(|e| e.to_string())?)),
+                "a number",
+            )
+            .add_flag("flag", "flag", 'f', "a flag")
+            .with_main(stub_main);
+        match parser.parse(Vec::from(args)) {
+            Err(e) => {
+                assert!(matches!(e, ParseError::ConversionFailed { .. }));
+                assert_eq!(e.argument(), Some("count"));
+                assert_eq!(e.position(), Some(1));
+                assert_eq!(
+                    e.to_string(),
+                    format!(
+                        "Bad Cmd Arguments: invalid value 'three' for count: invalid digit found in string\n\n{}",
+                        parser.help()
+                    )
+                );
+            }
+            Ok(_) => panic!("Should not have parsed"),
+        }
+    }
+
+    #[test]
+    fn parse_command_line_typed_arguments() {
+        let args: &[String] = &["--count".to_string(), "3".to_string(), "2.5".to_string()];
+        let parser: Parser<StubAction> = Parser::new("test", "doc")
+            .add_typed_default::<u8>("retries".to_string(), "5".to_string())
+            .add_typed_positional::<f64>("ratio", "a ratio")
+            .add_typed_optional::<i32>("count", "count", 'c', None, "a count")
+            .add_typed_optional::<u16>("port", "port", 'p', Some("8080"), "a port")
+            .with_main(stub_main);
+        let (result, _main) = parser.parse(Vec::from(args)).unwrap();
+        assert_eq!(*result.get_value::<u8>("retries"), 5);
+        assert_eq!(*result.get_value::<f64>("ratio"), 2.5);
+        assert_eq!(*result.get_value::<i32>("count"), 3);
+        assert_eq!(*result.get_value::<u16>("port"), 8080);
+        assert_eq!(
+            result
+                .try_get_value::<String>("count")
+                .unwrap_err()
+                .to_string(),
+            "value count is not of expected type alloc::string::String, it is a i32"
+        );
+    }
+
+    #[test]
+    fn parse_command_line_typed_optional_bad_value() {
+        let args: &[String] = &["-c".to_string(), "many".to_string()];
+        let parser: Parser<StubAction> = Parser::new("test", "doc")
+            .add_typed_optional::<i32>("count", "count", 'c', None, "a count")
+            .with_main(stub_main);
+        match parser.parse(Vec::from(args)) {
+            Err(ParseError::ConversionFailed {
+                argument,
+                value,
+                reason,
+                position,
+                ..
+            }) => {
+                assert_eq!(argument, "count");
+                assert_eq!(value, "many");
+                assert_eq!(reason, "invalid digit found in string");
+                assert_eq!(position, Some(1));
+            }
+            Err(e) => panic!("unexpected error {:?}", e),
+            Ok(_) => panic!("Should not have parsed"),
+        }
+    }
+
+    #[test]
+    fn parse_command_line_append_and_count() {
+        let args: &[String] = &[
+            "-v".to_string(),
+            "-I".to_string(),
+            "a".to_string(),
+            "--verbose".to_string(),
+            "-I".to_string(),
+            "b".to_string(),
+            "-v".to_string(),
+        ];
+        let parser: Parser<StubAction> = Parser::new("test", "doc")
+            .add_optional("include", "include", 'I', None, "include path")
+            .append()
+            .add_typed_optional::<u16>("port", "port", 'p', Some("80"), "ports")
+            .append()
+            .add_optional("define", "define", 'D', None, "defines")
+            .append()
+            .add_flag("verbose", "verbose", 'v', "verbosity")
+            .count()
+            .add_flag("quiet", "quiet", 'q', "quietness")
+            .count()
+            .with_main(stub_main);
+        let (result, _main) = parser.parse(Vec::from(args)).unwrap();
+        assert_eq!(result.get_values::<String>("include"), ["a", "b"]);
+        assert_eq!(result.get_values::<u16>("port"), [&80]);
+        assert!(result.get_values::<String>("define").is_empty());
+        assert_eq!(*result.get_value::<u8>("verbose"), 3);
+        assert_eq!(*result.get_value::<u8>("quiet"), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "append must directly follow an optional")]
+    fn append_after_flag_panics() {
+        let _: Parser<StubAction> = Parser::new("test", "doc")
+            .add_flag("verbose", "verbose", 'v', "verbosity")
+            .append();
+    }
+
+    fn get_copy_parser() -> Parser<StubAction> {
+        Parser::new("cp", "copies files")
+            .add_positional("sources", "files to copy")
+            .nargs(Nargs::OneOrMore)
+            .add_positional("destination", "target directory")
+            .add_flag("force", "force", 'f', "overwrite")
+            .with_main(stub_main)
+    }
+
+    #[test]
+    fn variadic_positional_leaves_trailing_positional_its_token() {
+        let args: &[String] = &[
+            "a".to_string(),
+            "-f".to_string(),
+            "b".to_string(),
+            "c".to_string(),
+            "dir".to_string(),
+        ];
+        let (results, _) = get_copy_parser().parse(Vec::from(args)).unwrap();
+        assert_eq!(
+            results.get_values::<String>("sources"),
+            vec![&"a".to_string(), &"b".to_string(), &"c".to_string()]
+        );
+        assert_eq!(results.get_value::<String>("destination"), "dir");
+        assert!(results.get_flag("force"));
+    }
+
+    #[test]
+    fn variadic_positional_reports_missing_trailing_positional() {
+        let args: &[String] = &["a".to_string()];
+        match get_copy_parser().parse(Vec::from(args)) {
+            Err(ParseError::MissingPositional { argument, .. }) => {
+                assert_eq!(argument, "destination")
+            }
+            Err(e) => panic!("unexpected error {:?}", e),
+            Ok(_) => panic!("Should not have parsed"),
+        }
+    }
+
+    #[test]
+    fn variadic_positional_conversion_error_points_at_token() {
+        let args: &[String] = &["1".to_string(), "-f".to_string(), "two".to_string()];
+        let parser: Parser<StubAction> = Parser::new("sum", "adds numbers")
+            .add_typed_positional::<i32>("numbers", "numbers to add")
+            .nargs(Nargs::ZeroOrMore)
+            .add_flag("force", "force", 'f', "force")
+            .with_main(stub_main);
+        let error = parser.parse(Vec::from(args)).err().unwrap();
+        assert_eq!(error.argument(), Some("numbers"));
+        assert_eq!(error.position(), Some(2));
+    }
+
+    #[test]
+    fn empty_variadic_positional_is_not_given() {
+        let parser = || -> Parser<StubAction> {
+            Parser::new("sum", "adds numbers")
+                .add_typed_positional::<i32>("numbers", "numbers to add")
+                .nargs(Nargs::ZeroOrMore)
+                .with_main(stub_main)
+        };
+        let (results, _) = parser().parse(Vec::new()).unwrap();
+        assert!(results.get_values::<i32>("numbers").is_empty());
+        assert!(!results.is_given("numbers"));
+        let (results, _) = parser().parse(vec!["1".to_string()]).unwrap();
+        assert!(results.is_given("numbers"));
+    }
+
+    #[test]
+    fn optional_positional_uses_default_value() {
+        let parser = || -> Parser<StubAction> {
+            Parser::new("cat", "prints a file")
+                .add_positional("file", "file to print")
+                .nargs(Nargs::Optional)
+                .default_value("-")
+                .with_main(stub_main)
+        };
+        let (results, _) = parser().parse(Vec::new()).unwrap();
+        assert_eq!(results.get_value::<String>("file"), "-");
+        let (results, _) = parser().parse(vec!["notes.txt".to_string()]).unwrap();
+        assert_eq!(results.get_value::<String>("file"), "notes.txt");
+    }
+
+    #[test]
+    fn fixed_count_positional_rejects_surplus_tokens() {
+        let args: &[String] = &["1".to_string(), "2".to_string(), "3".to_string()];
+        let parser: Parser<StubAction> = Parser::new("range", "doc")
+            .add_typed_positional::<u32>("bounds", "lower and upper bound")
+            .nargs(Nargs::Exactly(2))
+            .with_main(stub_main);
+        assert!(matches!(
+            parser.parse(Vec::from(args)),
+            Err(ParseError::UnexpectedArguments { position: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn variadic_positional_stops_at_action() {
+        let args: &[String] = &[
+            "a.txt".to_string(),
+            "b.txt".to_string(),
+            "compute".to_string(),
+            "stuff".to_string(),
+        ];
+        let parser: Parser<StubAction> = Parser::new("test", "doc")
+            .add_positional("files", "input files")
+            .nargs(Nargs::ZeroOrMore)
+            .add_action(
+                Parser::new("compute", "compute things")
+                    .add_positional("stuff", "stuff")
+                    .with_main(stub_main),
+            );
+        let (results, _) = parser.parse(Vec::from(args)).unwrap();
+        assert_eq!(results.get_values::<String>("files").len(), 2);
+        assert_eq!(results.get_action(), "compute");
+    }
+
+    #[test]
+    fn variadic_positional_in_help() {
+        let parser: Parser<StubAction> = get_copy_parser();
+        assert_eq!(
+            parser.build_usage_line(),
+            "usage: cp [sources+] [destination] {-f}"
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "nargs must directly follow a positional")]
+    fn nargs_after_flag_panics() {
+        let _: Parser<StubAction> = Parser::new("test", "doc")
+            .add_flag("verbose", "verbose", 'v', "verbosity")
+            .nargs(Nargs::OneOrMore);
+    }
+
+    #[test]
+    fn multi_value_optionals_mix_with_positionals() {
+        let parser = || -> Parser<StubAction> {
+            Parser::new("plot", "doc")
+                .add_positional("output", "output file")
+                .nargs(Nargs::Optional)
+                .add_typed_optional::<u32>("range", "range", 'r', Some("0 100"), "x range")
+                .nargs(Nargs::Exactly(2))
+                .add_optional("files", "files", 'f', None, "input files")
+                .nargs(Nargs::ZeroOrMore)
+                .add_optional("color", "color", 'c', Some("never"), "when to color")
+                .nargs(Nargs::Optional)
+                .const_value("always")
+                .with_main(stub_main)
+        };
+        let args: &[String] = &[
+            "--range".to_string(),
+            "1".to_string(),
+            "10".to_string(),
+            "out".to_string(),
+            "--files".to_string(),
+            "a".to_string(),
+            "b".to_string(),
+            "--color".to_string(),
+        ];
+        let (results, _) = parser().parse(Vec::from(args)).unwrap();
+        assert_eq!(results.get_array::<u32, 2>("range"), [&1, &10]);
+        assert_eq!(results.get_value::<String>("output"), "out");
+        assert_eq!(results.get_values::<String>("files").len(), 2);
+        assert_eq!(results.get_value::<String>("color"), "always");
+
+        let (results, _) = parser().parse(Vec::new()).unwrap();
+        assert_eq!(results.get_array::<u32, 2>("range"), [&0, &100]);
+        assert_eq!(results.get_value::<String>("color"), "never");
+    }
+
+    #[test]
+    #[should_panic(expected = "optional 'color' takes an optional value but has no const_value")]
+    fn optional_value_without_const_panics() {
+        let parser: Parser<StubAction> = Parser::new("ls", "lists files")
+            .add_action(Parser::new("files", "lists files").with_main(stub_main))
+            .add_action(
+                Parser::new("dirs", "lists directories")
+                    .add_optional("color", "color", 'c', Some("never"), "when to color")
+                    .nargs(Nargs::Optional)
+                    .with_main(stub_main),
+            );
+        let _ = parser.parse(vec!["files".to_string()]);
+    }
+
+    #[test]
+    fn multi_value_optional_conversion_error_points_at_token() {
+        let args: &[String] = &["-r".to_string(), "1".to_string(), "ten".to_string()];
+        let parser: Parser<StubAction> = Parser::new("plot", "doc")
+            .add_typed_optional::<u32>("range", "range", 'r', None, "x range")
+            .nargs(Nargs::Exactly(2))
+            .with_main(stub_main);
+        let error = parser.parse(Vec::from(args)).err().unwrap();
+        assert_eq!(error.argument(), Some("range"));
+        assert_eq!(error.position(), Some(2));
+    }
+
+    #[test]
+    #[should_panic(expected = "const_value must directly follow an optional")]
+    fn const_value_after_positional_panics() {
+        let _: Parser<StubAction> = Parser::new("test", "doc")
+            .add_positional("file", "a file")
+            .const_value("x");
+    }
+
+    #[test]
+    fn parse_attached_optional_values() {
+        let args: &[String] = &[
+            "--optional=long".to_string(),
+            "positional".to_string(),
+            "-f".to_string(),
+        ];
+        let (results, _) = get_basic_cmd_parser().parse(Vec::from(args)).unwrap();
+        assert_eq!(results.get_value::<String>("optional"), "long");
+        let args: &[String] = &["-oshort".to_string(), "positional".to_string()];
+        let (results, _) = get_basic_cmd_parser().parse(Vec::from(args)).unwrap();
+        assert_eq!(results.get_value::<String>("optional"), "short");
+        assert!(!results.get_flag("flag"));
+    }
+
+    #[test]
+    fn parse_separator_ends_switches() {
+        let args: &[String] = &["--".to_string(), "-f".to_string()];
+        let (results, _) = get_basic_cmd_parser().parse(Vec::from(args)).unwrap();
+        assert_eq!(results.get_value::<String>("positional"), "-f");
+        assert!(!results.get_flag("flag"));
+    }
+
+    #[test]
+    fn parse_separator_carries_into_actions() {
+        let args: &[String] = &[
+            "positional".to_string(),
+            "--".to_string(),
+            "compute".to_string(),
+            "--help".to_string(),
+        ];
+        let (results, _) = get_nested_parser().parse(Vec::from(args)).unwrap();
+        assert_eq!(results.get_value::<String>("stuff"), "--help");
+    }
+
+    #[test]
+    fn parse_flag_with_attached_value_is_an_error() {
+        let args: &[String] = &["positional".to_string(), "--flag=yes".to_string()];
+        let error = get_basic_cmd_parser()
+            .parse(Vec::from(args))
+            .err()
+            .expect("should not have parsed");
+        assert!(matches!(error, ParseError::UnexpectedValue { .. }));
+        assert_eq!(error.argument(), Some("flag"));
+        assert_eq!(error.position(), Some(1));
+    }
+
+    fn get_archive_parser() -> Parser<StubAction> {
+        Parser::new("tar", "archives files")
+            .add_flag("extract", "extract", 'x', "extract files")
+            .add_flag("verbose", "verbose", 'v', "list files")
+            .count()
+            .add_optional("file", "file", 'f', None, "archive file")
+            .with_main(stub_main)
+    }
+
+    #[test]
+    fn parse_short_cluster_sets_every_flag() {
+        let args: &[String] = &["-xvv".to_string(), "-f".to_string(), "a.tar".to_string()];
+        let (results, _) = get_archive_parser().parse(Vec::from(args)).unwrap();
+        assert!(results.get_flag("extract"));
+        assert_eq!(*results.get_value::<u8>("verbose"), 2);
+        assert_eq!(results.get_value::<String>("file"), "a.tar");
+    }
+
+    #[test]
+    fn parse_short_cluster_ends_with_optional() {
+        let args: &[String] = &["-xvf".to_string(), "a.tar".to_string()];
+        let (results, _) = get_archive_parser().parse(Vec::from(args)).unwrap();
+        assert!(results.get_flag("extract"));
+        assert_eq!(results.get_value::<String>("file"), "a.tar");
+
+        let args: &[String] = &["-xfa.tar".to_string()];
+        let (results, _) = get_archive_parser().parse(Vec::from(args)).unwrap();
+        assert_eq!(results.get_value::<String>("file"), "a.tar");
+    }
+
+    #[test]
+    fn parse_optional_rejects_switches_as_values() {
+        for token in ["--", "-f", "-h"] {
+            let args: &[String] = &["value".to_string(), "-o".to_string(), token.to_string()];
+            match get_basic_cmd_parser().parse(Vec::from(args)) {
+                Err(ParseError::MissingValue {
+                    argument, position, ..
+                }) => {
+                    assert_eq!(argument, "optional");
+                    assert_eq!(position, 1);
+                }
+                Err(e) => panic!("unexpected error {:?}", e),
+                Ok(_) => panic!("Should not have parsed"),
+            }
+        }
+        let args: &[String] = &["-o".to_string(), "-5".to_string(), "value".to_string()];
+        let (results, _) = get_basic_cmd_parser().parse(Vec::from(args)).unwrap();
+        assert_eq!(results.get_value::<String>("optional"), "-5");
+    }
+
+    #[test]
+    fn parse_short_cluster_optional_missing_value() {
+        let args: &[String] = &["-v".to_string(), "-xf".to_string()];
+        assert!(matches!(
+            get_archive_parser().parse(Vec::from(args)),
+            Err(ParseError::MissingValue { position: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn parse_short_cluster_rejects_unknown_char() {
+        let args: &[String] = &["-f".to_string(), "a.tar".to_string(), "-xzv".to_string()];
+        match get_archive_parser().parse(Vec::from(args)) {
+            Err(ParseError::UnknownOption {
+                option, position, ..
+            }) => {
+                assert_eq!(option, "-z");
+                assert_eq!(position, 2);
+            }
+            Err(e) => panic!("unexpected error {:?}", e),
+            Ok(_) => panic!("Should not have parsed"),
+        }
+    }
+
+    #[test]
+    fn parse_unknown_long_option_is_an_error() {
+        let args: &[String] = &["positional".to_string(), "--bogus=1".to_string()];
+        match get_basic_cmd_parser().parse(Vec::from(args)) {
+            Err(ParseError::UnknownOption {
+                option, position, ..
+            }) => {
+                assert_eq!(option, "--bogus=1");
+                assert_eq!(position, 1);
+            }
+            Err(e) => panic!("unexpected error {:?}", e),
+            Ok(_) => panic!("Should not have parsed"),
+        }
+    }
+
+    #[test]
+    fn parse_value_sharing_short_name_is_no_switch() {
+        // the second char used to be compared against the short names
+        let args: &[String] = &["xfoo".to_string()];
+        let (results, _) = get_basic_cmd_parser().parse(Vec::from(args)).unwrap();
+        assert_eq!(results.get_value::<String>("positional"), "xfoo");
+        assert!(!results.get_flag("flag"));
+    }
+
+    #[test]
+    fn parse_negative_numbers_as_values() {
+        let args: &[String] = &[
+            "-5".to_string(),
+            "--offsets".to_string(),
+            "-1".to_string(),
+            "-.5".to_string(),
+        ];
+        let (results, _) = Parser::<StubAction>::new("shift", "doc")
+            .add_typed_positional::<i32>("amount", "amount")
+            .add_typed_optional::<f64>("offsets", "offsets", 'o', None, "offsets")
+            .nargs(Nargs::OneOrMore)
+            .with_main(stub_main)
+            .parse(Vec::from(args))
+            .unwrap();
+        assert_eq!(*results.get_value::<i32>("amount"), -5);
+        assert_eq!(results.get_values::<f64>("offsets"), vec![&-1.0, &-0.5]);
+    }
+
+    #[test]
+    fn parse_negative_number_is_switch_with_digit_short() {
+        let parser = || -> Parser<StubAction> {
+            Parser::new("head", "doc")
+                .add_positional("value", "a value")
+                .add_flag("one", "one", '1', "only one")
+                .with_main(stub_main)
+        };
+        let args: &[String] = &["-1".to_string(), "x".to_string()];
+        let (results, _) = parser().parse(Vec::from(args)).unwrap();
+        assert!(results.get_flag("one"));
+        let args: &[String] = &["-5".to_string()];
+        assert!(matches!(
+            parser().parse(Vec::from(args)),
+            Err(ParseError::UnknownOption { position: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn parse_unknown_action_suggests_closest_action() {
+        let args: &[String] = &["positional".to_string(), "compte".to_string()];
+        let error = get_nested_parser()
+            .parse(Vec::from(args))
+            .err()
+            .expect("should not have parsed");
+        assert!(matches!(error, ParseError::UnknownAction { .. }));
+        assert_eq!(error.suggestion(), Some("compute"));
+    }
+
+    #[test]
+    fn parse_unknown_option_suggests_closest_long_name() {
+        let args: &[String] = &["positional".to_string(), "--optinal=x".to_string()];
+        let error = get_basic_cmd_parser()
+            .parse(Vec::from(args))
+            .err()
+            .expect("should not have parsed");
+        assert_eq!(error.suggestion(), Some("--optional"));
+
+        let args: &[String] = &["positional".to_string(), "--zzz".to_string()];
+        let error = get_basic_cmd_parser()
+            .parse(Vec::from(args))
+            .err()
+            .expect("should not have parsed");
+        assert_eq!(error.suggestion(), None);
+    }
+
+    fn get_abbreviating_parser() -> Parser<StubAction> {
+        Parser::new("test", "doc")
+            .allow_abbrev()
+            .add_optional("verbosity", "verbosity", 'l', Some("1"), "log level")
+            .add_flag("verbose", "verbose", 'v', "print more")
+            .add_flag("dry", "dry-run", 'n', "change nothing")
+            .add_flag("version", "version", 'V', "print the version")
+            .with_main(stub_main)
+    }
+
+    #[test]
+    fn parse_unique_prefix_with_allow_abbrev() {
+        let args: &[String] = &["--dry".to_string(), "--verbosi=3".to_string()];
+        let (results, _) = get_abbreviating_parser().parse(Vec::from(args)).unwrap();
+        assert!(results.get_flag("dry"));
+        assert_eq!(results.get_value::<String>("verbosity"), "3");
+    }
+
+    #[test]
+    fn parse_exact_name_wins_over_prefix() {
+        let args: &[String] = &["--verbose".to_string()];
+        let (results, _) = get_abbreviating_parser().parse(Vec::from(args)).unwrap();
+        assert!(results.get_flag("verbose"));
+    }
+
+    #[test]
+    fn parse_ambiguous_prefix_lists_candidates() {
+        let args: &[String] = &["--ver".to_string()];
+        match get_abbreviating_parser().parse(Vec::from(args)) {
+            Err(ParseError::AmbiguousOption {
+                option,
+                candidates,
+                position,
+                ..
+            }) => {
+                assert_eq!(option, "--ver");
+                assert_eq!(candidates, ["--verbosity", "--verbose", "--version"]);
+                assert_eq!(position, 0);
+            }
+            Err(e) => panic!("unexpected error {:?}", e),
+            Ok(_) => panic!("Should not have parsed"),
+        }
+    }
+
+    #[test]
+    fn parse_prefix_without_allow_abbrev_is_unknown() {
+        let args: &[String] = &["positional".to_string(), "--fl".to_string()];
+        assert!(matches!(
+            get_basic_cmd_parser().parse(Vec::from(args)),
+            Err(ParseError::UnknownOption { .. })
+        ));
+    }
+
+    fn get_aliased_parser() -> Parser<StubAction> {
+        Parser::new("pkg", "manages packages")
+            .allow_action_abbrev()
+            .add_action(
+                Parser::new("remove", "removes a package")
+                    .alias("rm")
+                    .with_main(stub_main),
+            )
+            .add_action(Parser::new("rebuild", "rebuilds a package").with_main(stub_main))
+            .add_action(Parser::new("list", "lists packages").with_main(stub_main))
+    }
+
+    #[test]
+    fn parse_action_alias_records_action_name() {
+        let (results, _) = get_aliased_parser().parse(vec!["rm".to_string()]).unwrap();
+        assert_eq!(results.get_action(), "remove");
+    }
+
+    #[test]
+    fn parse_action_unique_prefix() {
+        let (results, _) = get_aliased_parser().parse(vec!["rem".to_string()]).unwrap();
+        assert_eq!(results.get_action(), "remove");
+        let (results, _) = get_aliased_parser().parse(vec!["l".to_string()]).unwrap();
+        assert_eq!(results.get_action(), "list");
+    }
+
+    #[test]
+    fn parse_action_prefix_left_to_positionals() {
+        let parser = Parser::new("pkg", "manages packages")
+            .allow_action_abbrev()
+            .add_positional("package", "package to work on")
+            .nargs(Nargs::Optional)
+            .add_action(Parser::new("list", "lists packages").with_main(stub_main));
+        let args: &[String] = &["li".to_string(), "list".to_string()];
+        let (results, _) = parser.parse(Vec::from(args)).unwrap();
+        assert_eq!(results.get_value::<String>("package"), "li");
+        assert_eq!(results.get_action(), "list");
+    }
+
+    #[test]
+    fn parse_action_ambiguous_prefix_lists_candidates() {
+        match get_aliased_parser().parse(vec!["re".to_string()]) {
+            Err(ParseError::AmbiguousAction {
+                action, candidates, ..
+            }) => {
+                assert_eq!(action, "re");
+                assert_eq!(candidates, ["remove", "rebuild"]);
+            }
+            Err(e) => panic!("unexpected error {:?}", e),
+            Ok(_) => panic!("Should not have parsed"),
+        }
+    }
+
+    #[test]
+    fn parse_action_prefix_needs_opt_in() {
+        let args: &[String] = &["positional".to_string(), "comp".to_string()];
+        assert!(matches!(
+            get_nested_parser().parse(Vec::from(args)),
+            Err(ParseError::UnknownAction { .. })
+        ));
+    }
+
+    #[test]
+    fn parse_unknown_action_suggests_alias() {
+        let error = get_aliased_parser()
+            .parse(vec!["mr".to_string()])
+            .err()
+            .expect("should not have parsed");
+        assert_eq!(error.suggestion(), Some("rm"));
+    }
+
+    #[test]
+    fn aliases_in_help() {
+        let expected = r#"pkg - manages packages
+
+usage: pkg remove|rm,rebuild,list
+
+remove,rm             removes a package
+rebuild               rebuilds a package
+list                  lists packages
+"#;
+        assert_eq!(get_aliased_parser().help(), expected);
+    }
+
+    fn get_required_parser() -> Parser<StubAction> {
+        Parser::new("deploy", "deploys")
+            .add_optional("target", "target", 't', Some("staging"), "where to deploy")
+            .required()
+            .add_optional("region", "region", 'r', None, "region")
+            .add_optional("key", "key", 'k', None, "api key")
+            .required()
+            .with_main(stub_main)
+    }
+
+    #[test]
+    fn parse_lists_all_missing_required_optionals() {
+        match get_required_parser().parse(Vec::new()) {
+            Err(ParseError::MissingRequired { arguments, .. }) => {
+                assert_eq!(arguments, ["-t/--target", "-k/--key"])
+            }
+            Err(e) => panic!("unexpected error {:?}", e),
+            Ok(_) => panic!("Should not have parsed"),
+        }
+    }
+
+    #[test]
+    fn parse_required_optionals_given() {
+        let args: &[String] = &["-kxyz".to_string(), "--target=prod".to_string()];
+        let (results, _) = get_required_parser().parse(Vec::from(args)).unwrap();
+        assert_eq!(results.get_value::<String>("target"), "prod");
+        assert_eq!(results.get_value::<String>("key"), "xyz");
+    }
+
+    #[test]
+    fn missing_required_display_includes_usage() {
+        let args: &[String] = &["-k".to_string(), "xyz".to_string()];
+        let error = get_required_parser()
+            .parse(Vec::from(args))
+            .err()
+            .expect("should not have parsed");
+        let expected = r#"Bad Cmd Arguments: the following arguments are required: -t/--target
 
-    type StubAction = dyn FnOnce() -> Result<(), String>;
+deploy - deploys
 
-    fn stub_main() -> Result<(), String> {
-        Ok(())
+usage: deploy {-t,-r,-k}
+
+-t,--target           where to deploy (required)
+-r,--region           region
+-k,--key              api key (required)
+"#;
+        assert_eq!(error.to_string(), expected);
     }
 
-    fn get_basic_cmd_parser() -> Parser<StubAction> {
-        Parser::new("test", "I am a test")
-            .add_default("default".to_string(), "test".to_string())
-            .add_positional("positional", "I am the positional")
-            .add_optional(
-                "optional",
-                "optional",
-                'o',
-                Some("default"),
-                "I am the optional",
-            )
-            .add_flag("flag", "flag", 'f', "I am the flag")
+    fn get_exclusive_parser(required: bool) -> Parser<StubAction> {
+        Parser::new("dump", "dumps the data")
+            .add_flag("verbose", "verbose", 'v', "print more")
+            .add_mutually_exclusive_group(required, |group| {
+                group
+                    .add_optional("format", "format", 'f', Some("text"), "output format")
+                    .add_flag("json", "json", 'j', "print json")
+                    .add_flag("yaml", "yaml", 'y', "print yaml")
+            })
             .with_main(stub_main)
     }
 
-    fn get_nested_parser() -> Parser<StubAction> {
-        get_basic_cmd_parser().add_action(
-            Parser::new("compute", "I am da computaaah")
-                .add_positional("stuff", "stuff indeed")
-                .with_main(stub_main),
-        )
+    #[test]
+    fn parse_exclusive_group_rejects_two_members() {
+        let args: &[String] = &["--json".to_string(), "-v".to_string(), "-y".to_string()];
+        match get_exclusive_parser(false).parse(Vec::from(args)) {
+            Err(ParseError::MutuallyExclusive { arguments, .. }) => {
+                assert_eq!(arguments, ["-j/--json", "-y/--yaml"])
+            }
+            Err(e) => panic!("unexpected error {:?}", e),
+            Ok(_) => panic!("Should not have parsed"),
+        }
     }
 
     #[test]
-    fn parse_command_line_wrong_action() {
+    fn parse_exclusive_group_member_defaults_do_not_conflict() {
+        let args: &[String] = &["--yaml".to_string()];
+        let (results, _) = get_exclusive_parser(false).parse(Vec::from(args)).unwrap();
+        assert!(results.get_flag("yaml"));
+        assert!(!results.get_flag("json"));
+        assert_eq!(results.get_value::<String>("format"), "text");
+    }
+
+    fn get_layered_exclusive_parser(config: &str) -> Parser<StubAction> {
+        Parser::new("dump", "dumps the data")
+            .with_config_file(config)
+            .add_mutually_exclusive_group(false, |group| {
+                group
+                    .add_flag("json", "json", 'j', "print json")
+                    .env("DUMP_JSON")
+                    .add_flag("yaml", "yaml", 'y', "print yaml")
+            })
+            .with_main(stub_main)
+    }
+
+    #[test]
+    fn parse_exclusive_group_member_from_config_is_overridden() {
+        let dir = TestDir::new("parse_exclusive_group_member_from_config_is_overridden");
+        let config = dir.write("dump.toml", "json = true\n");
+        let args: &[String] = &["--yaml".to_string()];
+        let (results, _) = get_layered_exclusive_parser(&config)
+            .with_env_lookup(|_| None)
+            .parse(Vec::from(args))
+            .unwrap();
+        assert!(results.get_flag("yaml"));
+        assert!(!results.get_flag("json"));
+        assert_eq!(results.value_source("json"), Some(ValueSource::Default));
+        let (results, _) = get_layered_exclusive_parser(&config)
+            .with_env_lookup(|_| None)
+            .parse(Vec::new())
+            .unwrap();
+        assert!(results.get_flag("json"));
+        let config = dir.write("both.toml", "json = true\nyaml = true\n");
+        match get_layered_exclusive_parser(&config)
+            .with_env_lookup(|_| None)
+            .parse(Vec::new())
+        {
+            Err(ParseError::MutuallyExclusive { arguments, .. }) => {
+                assert_eq!(arguments, ["-j/--json", "-y/--yaml"])
+            }
+            Err(e) => panic!("unexpected error {:?}", e),
+            Ok(_) => panic!("Should not have parsed"),
+        }
+    }
+
+    #[test]
+    fn parse_exclusive_group_ignores_flag_turned_off_in_environment() {
+        let args: &[String] = &["--yaml".to_string()];
+        let (results, _) = get_layered_exclusive_parser("/nonexistent/dump.toml")
+            .with_env_lookup(|var| (var == "DUMP_JSON").then(|| "0".to_string()))
+            .parse(Vec::from(args))
+            .unwrap();
+        assert!(results.get_flag("yaml"));
+        assert!(!results.get_flag("json"));
+    }
+
+    #[test]
+    fn parse_required_exclusive_group_needs_a_member() {
+        match get_exclusive_parser(true).parse(Vec::new()) {
+            Err(ParseError::MissingRequired { arguments, .. }) => {
+                assert_eq!(arguments, ["-f/--format | -j/--json | -y/--yaml"])
+            }
+            Err(e) => panic!("unexpected error {:?}", e),
+            Ok(_) => panic!("Should not have parsed"),
+        }
+        let args: &[String] = &["-fcsv".to_string()];
+        assert!(get_exclusive_parser(true).parse(Vec::from(args)).is_ok());
+    }
+
+    #[test]
+    fn exclusive_group_in_usage() {
+        assert_eq!(
+            get_exclusive_parser(false).build_usage_line(),
+            "usage: dump {-v} [--format | --json | --yaml]"
+        );
+        assert_eq!(
+            get_exclusive_parser(true).build_usage_line(),
+            "usage: dump {-v} (--format | --json | --yaml)"
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "mutually exclusive groups hold only optionals and flags")]
+    fn exclusive_group_with_positional_panics() {
+        let _: Parser<StubAction> = Parser::new("test", "doc")
+            .add_mutually_exclusive_group(false, |group| group.add_positional("file", "a file"));
+    }
+
+    #[test]
+    fn argument_groups_get_titled_sections_in_help() {
+        let parser: Parser<StubAction> = Parser::new("serve", "serves files")
+            .add_positional("root", "directory to serve")
+            .add_argument_group("Network options", "", |group| {
+                group
+                    .add_optional("port", "port", 'p', Some("8080"), "tcp port")
+                    .add_flag("ipv6", "ipv6", '6', "listen on ipv6")
+            })
+            .add_flag("verbose", "verbose", 'v', "print more")
+            .add_argument_group("Output options", "How responses are written.", |group| {
+                group.add_flag("gzip", "gzip", 'z', "compress responses")
+            })
+            .with_main(stub_main);
+        let expected = r#"serve - serves files
+
+usage: serve [root] {-p,-6,-v,-z}
+
+[root]                directory to serve
+-v,--verbose          print more
+
+Network options:
+-p,--port             tcp port
+-6,--ipv6             listen on ipv6
+
+Output options:
+How responses are written.
+-z,--gzip             compress responses
+"#;
+        assert_eq!(parser.help(), expected);
+    }
+
+    #[test]
+    #[should_panic(expected = "argument groups cannot be nested")]
+    fn nested_argument_groups_panic() {
+        let _: Parser<StubAction> = Parser::new("test", "doc").add_argument_group("a", "", |a| {
+            a.add_argument_group("b", "", |b| b.add_flag("flag", "flag", 'f', "a flag"))
+        });
+    }
+
+    fn get_choices_parser() -> Parser<StubAction> {
+        Parser::new("log", "writes logs")
+            .add_positional("target", "where to write")
+            .choices(&["file", "syslog"])
+            .add_optional("level", "level", 'l', Some("info"), "log level")
+            .choices(&["debug", "info", "warn"])
+            .add_flag("quiet", "quiet", 'q', "print nothing")
+            .with_main(stub_main)
+    }
+
+    #[test]
+    fn parse_choices_rejects_other_tokens() {
+        let args: &[String] = &["file".to_string(), "-l".to_string(), "trace".to_string()];
+        let error = get_choices_parser()
+            .parse(Vec::from(args))
+            .err()
+            .expect("should not have parsed");
+        assert_eq!(error.position(), Some(2));
+        assert!(error.to_string().starts_with(
+            "Bad Cmd Arguments: invalid choice 'trace' for level (choose from debug, info, warn)"
+        ));
+        let args: &[String] = &["mail".to_string()];
+        match get_choices_parser().parse(Vec::from(args)) {
+            Err(ParseError::InvalidChoice { argument, .. }) => assert_eq!(argument, "target"),
+            Err(e) => panic!("unexpected error {:?}", e),
+            Ok(_) => panic!("Should not have parsed"),
+        }
+    }
+
+    #[test]
+    fn parse_choices_accepts_listed_tokens() {
+        let args: &[String] = &["syslog".to_string(), "--level=warn".to_string()];
+        let (results, _) = get_choices_parser().parse(Vec::from(args)).unwrap();
+        assert_eq!(results.get_value::<String>("target"), "syslog");
+        assert_eq!(results.get_value::<String>("level"), "warn");
+    }
+
+    #[test]
+    fn choices_in_usage_and_help() {
+        let expected = r#"log - writes logs
+
+usage: log [target {file,syslog}] {-q} [-l {debug,info,warn}]
+
+[target {file,syslog}] where to write
+-l,--level {debug,info,warn} log level
+-q,--quiet            print nothing
+"#;
+        assert_eq!(get_choices_parser().help(), expected);
+    }
+
+    fn get_env_parser() -> Parser<StubAction> {
+        Parser::new("deploy", "deploys")
+            .add_optional("timeout", "timeout", 't', Some("30"), "seconds to wait")
+            .env("DEPLOY_TIMEOUT")
+            .add_optional("token", "token", 'k', None, "api token")
+            .env("DEPLOY_TOKEN")
+            .required()
+            .add_flag("dry", "dry-run", 'd', "change nothing")
+            .env("DEPLOY_DRY_RUN")
+            .add_flag("verbose", "verbose", 'v', "print more")
+            .count()
+            .env("DEPLOY_VERBOSE")
+            .with_main(stub_main)
+    }
+
+    #[test]
+    fn parse_optional_falls_back_to_environment() {
+        let lookup: EnvLookup = |var| match var {
+            "DEPLOY_TIMEOUT" => Some("60".to_string()),
+            "DEPLOY_TOKEN" => Some("secret".to_string()),
+            _ => None,
+        };
+        let parser = get_env_parser().with_env_lookup(lookup);
+        let (results, _) = parser.parse(Vec::new()).unwrap();
+        assert_eq!(results.get_value::<String>("timeout"), "60");
+        assert_eq!(results.get_value::<String>("token"), "secret");
+        assert_eq!(
+            results.value_source("token"),
+            Some(ValueSource::Environment)
+        );
+        let parser = get_env_parser().with_env_lookup(lookup);
+        let args: &[String] = &["-t".to_string(), "5".to_string()];
+        let (results, _) = parser.parse(Vec::from(args)).unwrap();
+        assert_eq!(results.get_value::<String>("timeout"), "5");
+        assert_eq!(
+            results.value_source("timeout"),
+            Some(ValueSource::CommandLine)
+        );
+    }
+
+    #[test]
+    fn parse_default_applies_without_environment() {
+        let parser = get_env_parser().with_env_lookup(|_| None);
+        let args: &[String] = &["--token=abc".to_string()];
+        let (results, _) = parser.parse(Vec::from(args)).unwrap();
+        assert_eq!(results.get_value::<String>("timeout"), "30");
+        assert_eq!(results.value_source("timeout"), Some(ValueSource::Default));
+        assert!(!results.get_flag("dry"));
+        let parser = get_env_parser().with_env_lookup(|_| None);
+        match parser.parse(Vec::new()) {
+            Err(ParseError::MissingRequired { arguments, .. }) => {
+                assert_eq!(arguments, ["-k/--token"])
+            }
+            Err(e) => panic!("unexpected error {:?}", e),
+            Ok(_) => panic!("Should not have parsed"),
+        }
+    }
+
+    #[test]
+    fn parse_flags_from_truthy_and_falsy_environment() {
+        let parser = get_env_parser().with_env_lookup(|var| match var {
+            "DEPLOY_TOKEN" => Some("secret".to_string()),
+            "DEPLOY_DRY_RUN" => Some("Yes".to_string()),
+            "DEPLOY_VERBOSE" => Some("3".to_string()),
+            _ => None,
+        });
+        let (results, _) = parser.parse(Vec::new()).unwrap();
+        assert!(results.get_flag("dry"));
+        assert_eq!(*results.get_value::<u8>("verbose"), 3);
+        let parser = get_env_parser().with_env_lookup(|var| match var {
+            "DEPLOY_TOKEN" => Some("secret".to_string()),
+            "DEPLOY_DRY_RUN" => Some("off".to_string()),
+            "DEPLOY_VERBOSE" => Some("false".to_string()),
+            _ => None,
+        });
+        let (results, _) = parser.parse(Vec::new()).unwrap();
+        assert!(!results.get_flag("dry"));
+        assert_eq!(results.value_source("dry"), Some(ValueSource::Environment));
+        assert_eq!(*results.get_value::<u8>("verbose"), 0);
+        let parser = get_env_parser().with_env_lookup(|var| match var {
+            "DEPLOY_TOKEN" => Some("secret".to_string()),
+            "DEPLOY_DRY_RUN" => Some("maybe".to_string()),
+            _ => None,
+        });
+        match parser.parse(Vec::new()) {
+            Err(ParseError::ConversionFailed {
+                argument, position, ..
+            }) => {
+                assert_eq!(argument, "dry");
+                assert_eq!(position, None);
+            }
+            Err(e) => panic!("unexpected error {:?}", e),
+            Ok(_) => panic!("Should not have parsed"),
+        }
+    }
+
+    #[test]
+    fn env_var_shown_in_help() {
+        let help = get_env_parser().help();
+        assert!(help.contains("-t,--timeout          seconds to wait [env: DEPLOY_TIMEOUT]\n"));
+        assert!(help.contains("-k,--token            api token [env: DEPLOY_TOKEN] (required)\n"));
+        assert!(help.contains("-d,--dry-run          change nothing [env: DEPLOY_DRY_RUN]\n"));
+    }
+
+    fn get_config_parser(config: &str) -> Parser<StubAction> {
+        Parser::new("tool", "a tool")
+            .with_config_file(config)
+            .add_optional("config", "config", 'c', None, "config file")
+            .config_file()
+            .add_optional("level", "level", 'l', Some("info"), "log level")
+            .add_optional("color", "color", 'C', Some("auto"), "coloring")
+            .env("TOOL_COLOR")
+            .add_action(
+                Parser::new("serve", "serves")
+                    .add_optional("port", "port", 'p', Some("80"), "tcp port")
+                    .add_optional("hosts", "hosts", 'H', None, "hosts to bind")
+                    .nargs(Nargs::OneOrMore)
+                    .add_flag("daemon", "daemon", 'd', "run in the background")
+                    .env("TOOL_DAEMON")
+                    .with_main(stub_main),
+            )
+    }
+
+    #[test]
+    fn parse_config_file_precedence_and_sections() {
+        let dir = TestDir::new("parse_config_file_precedence_and_sections");
+        let config = dir.write(
+            "precedence.toml",
+            "level = \"debug\"\ncolor = never\n\n[serve]\nport = 8080\nhosts = [\"a\", \"b\"]\ndaemon = yes\n",
+        );
+        let args: &[String] = &["serve".to_string(), "-p".to_string(), "9000".to_string()];
+        let (results, _) = get_config_parser(&config)
+            .with_env_lookup(|var| (var == "TOOL_COLOR").then(|| "always".to_string()))
+            .parse(Vec::from(args))
+            .unwrap();
+        assert_eq!(results.get_value::<String>("level"), "debug");
+        assert_eq!(results.value_source("level"), Some(ValueSource::ConfigFile));
+        assert_eq!(results.get_value::<String>("color"), "always");
+        assert_eq!(results.get_value::<String>("port"), "9000");
+        assert_eq!(results.get_values::<String>("hosts"), ["a", "b"]);
+        assert!(results.get_flag("daemon"));
+    }
+
+    #[test]
+    fn parse_falsy_environment_flag_overrides_config_file() {
+        let dir = TestDir::new("parse_falsy_environment_flag_overrides_config_file");
+        let config = dir.write("falsy_env.toml", "[serve]\ndaemon = true\n");
+        let args: &[String] = &["serve".to_string()];
+        let (results, _) = get_config_parser(&config)
+            .with_env_lookup(|var| (var == "TOOL_DAEMON").then(|| "false".to_string()))
+            .parse(Vec::from(args))
+            .unwrap();
+        assert!(!results.get_flag("daemon"));
+        assert_eq!(
+            results.value_source("daemon"),
+            Some(ValueSource::Environment)
+        );
+    }
+
+    #[test]
+    fn parse_config_option_overrides_config_file() {
+        let dir = TestDir::new("parse_config_option_overrides_config_file");
+        let config = dir.write("base.toml", "level = \"debug\"\ncolor = never\n");
+        let explicit = dir.write("explicit.json", r#"{ "level": "warn" }"#);
+        let args: &[String] = &["--config".to_string(), explicit, "serve".to_string()];
+        let (results, _) = get_config_parser(&config).parse(Vec::from(args)).unwrap();
+        assert_eq!(results.get_value::<String>("level"), "warn");
+        assert_eq!(results.get_value::<String>("color"), "never");
+        assert_eq!(results.get_value::<String>("port"), "80");
+    }
+
+    #[test]
+    fn parse_missing_config_files() {
+        let args: &[String] = &["serve".to_string()];
+        let (results, _) = get_config_parser("/nonexistent/tool.toml")
+            .parse(Vec::from(args))
+            .unwrap();
+        assert_eq!(results.get_value::<String>("level"), "info");
         let args: &[String] = &[
-            "positional".to_string(),
-            "-o".to_string(),
-            "optional".to_string(),
-            "-f".to_string(),
-            "wrong-action".to_string(),
+            "-c".to_string(),
+            "/nonexistent/tool.toml".to_string(),
+            "serve".to_string(),
         ];
-        let parser: Parser<StubAction> = get_nested_parser();
-        assert!(parser.parse(Vec::from(args)).is_err())
+        match get_config_parser("/nonexistent/tool.toml").parse(Vec::from(args)) {
+            Err(ParseError::ConfigFailed { path, line, .. }) => {
+                assert_eq!(path, "/nonexistent/tool.toml");
+                assert_eq!(line, None);
+            }
+            Err(e) => panic!("unexpected error {:?}", e),
+            Ok(_) => panic!("Should not have parsed"),
+        }
     }
 
     #[test]
-    fn parse_command_line_missing_action() {
+    fn parse_config_file_rejects_unknown_keys_and_sections() {
+        let dir = TestDir::new("parse_config_file_rejects_unknown_keys_and_sections");
+        let config = dir.write("unknown_key.toml", "[serve]\n\nprot = 8080\n");
+        let args: &[String] = &["serve".to_string()];
+        let error = get_config_parser(&config)
+            .parse(Vec::from(args))
+            .err()
+            .expect("should not have parsed");
+        assert_eq!(error.action_path(), ["tool", "serve"]);
+        assert!(error.to_string().starts_with(&format!(
+            "Bad Cmd Arguments: config file {}, line 3: unknown option 'prot', did you mean port?",
+            config
+        )));
+        let config = dir.write("unknown_section.toml", "[srve]\nport = 8080\n");
+        match get_config_parser(&config).parse(Vec::from(args)) {
+            Err(ParseError::ConfigFailed { reason, .. }) => {
+                assert_eq!(reason, "unknown action section [srve]")
+            }
+            Err(e) => panic!("unexpected error {:?}", e),
+            Ok(_) => panic!("Should not have parsed"),
+        }
+    }
+
+    #[test]
+    fn parse_splices_response_files() {
+        let dir = TestDir::new("parse_splices_response_files");
+        let args_file = dir.write("response.args", "-o 'from file'\n-f\n");
+        let parser = get_basic_cmd_parser().fromfile_prefix('@');
+        let args: &[String] = &["value".to_string(), format!("@{}", args_file)];
+        let (results, _) = parser.parse(Vec::from(args)).unwrap();
+        assert_eq!(results.get_value::<String>("optional"), "from file");
+        assert!(results.get_flag("flag"));
         let args: &[String] = &[
-            "positional".to_string(),
-            "-o".to_string(),
-            "optional".to_string(),
-            "-f".to_string(),
+            "value".to_string(),
+            "@/nonexistent/response.args".to_string(),
         ];
-        let parser: Parser<StubAction> = get_nested_parser();
-        assert!(parser.parse(Vec::from(args)).is_err())
+        match parser.parse(Vec::from(args)) {
+            Err(e @ ParseError::FromFileFailed { .. }) => {
+                assert_eq!(e.position(), Some(1));
+                assert_eq!(e.action_path(), ["test"]);
+            }
+            Err(e) => panic!("unexpected error {:?}", e),
+            Ok(_) => panic!("Should not have parsed"),
+        }
+        let args: &[String] = &["@literal".to_string()];
+        let (results, _) = get_basic_cmd_parser().parse(Vec::from(args)).unwrap();
+        assert_eq!(results.get_value::<String>("positional"), "@literal");
+    }
+
+    fn get_constrained_parser() -> Parser<StubAction> {
+        Parser::new("server", "runs servers")
+            .add_optional("config", "config", 'c', None, "config file")
+            .add_action(
+                Parser::new("serve", "serves requests")
+                    .add_optional("key", "key", 'k', None, "tls key")
+                    .requires("cert")
+                    .add_optional("cert", "cert", 'C', None, "tls certificate")
+                    .add_optional("port", "port", 'p', Some("8080"), "tcp port")
+                    .conflicts_with("socket")
+                    .add_optional("socket", "socket", 's', None, "unix socket")
+                    .add_optional("password", "password", 'P', None, "password")
+                    .required_unless("config")
+                    .with_main(stub_main),
+            )
     }
 
     #[test]
-    fn parse_command_line_full_action() {
+    fn parse_constraint_on_root_option_from_sub_action() {
         let args: &[String] = &[
-            "positional".to_string(),
-            "-o".to_string(),
-            "optional".to_string(),
-            "-f".to_string(),
+            "--config".to_string(),
+            "server.toml".to_string(),
+            "serve".to_string(),
         ];
-        let parser: Parser<StubAction> = get_basic_cmd_parser();
-        match parser.parse(Vec::from(args)) {
-            Ok((result, _main)) => {
-                assert_eq!(result.get_action(), "test");
-                assert_eq!(result.get_value::<String>("positional"), "positional");
-                assert_eq!(result.get_value::<String>("optional"), "optional");
-                assert!(result.get_flag("flag"));
+        assert!(get_constrained_parser().parse(Vec::from(args)).is_ok());
+        let args: &[String] = &["serve".to_string()];
+        match get_constrained_parser().parse(Vec::from(args)) {
+            Err(e @ ParseError::MissingRequired { .. }) => {
+                assert_eq!(e.action_path(), ["server", "serve"]);
+                assert!(e.to_string().contains("-P/--password | -c/--config"));
             }
-            Err(e) => panic!("{}", e),
+            Err(e) => panic!("unexpected error {:?}", e),
+            Ok(_) => panic!("Should not have parsed"),
         }
     }
 
     #[test]
-    fn parse_command_line_full_sub_action() {
+    fn parse_requires_constraint() {
         let args: &[String] = &[
-            "positional".to_string(),
-            "-o".to_string(),
-            "optional".to_string(),
-            "-f".to_string(),
-            "compute".to_string(),
-            "values".to_string(),
+            "serve".to_string(),
+            "-P".to_string(),
+            "secret".to_string(),
+            "--key".to_string(),
+            "a.pem".to_string(),
         ];
-        let parser: Parser<StubAction> = get_nested_parser();
-        match parser.parse(Vec::from(args)) {
-            Ok((result, _main)) => {
-                assert_eq!(result.get_action(), "compute");
-                assert_eq!(result.get_value::<String>("positional"), "positional");
-                assert_eq!(result.get_value::<String>("optional"), "optional");
-                assert_eq!(result.get_value::<String>("stuff"), "values");
-                assert!(result.get_flag("flag"));
+        match get_constrained_parser().parse(Vec::from(args)) {
+            Err(ParseError::MissingDependency {
+                argument, required, ..
+            }) => {
+                assert_eq!(argument, "-k/--key");
+                assert_eq!(required, "-C/--cert");
             }
-            Err(e) => panic!("{}", e),
+            Err(e) => panic!("unexpected error {:?}", e),
+            Ok(_) => panic!("Should not have parsed"),
         }
+        let args: &[String] = &[
+            "serve".to_string(),
+            "-P".to_string(),
+            "secret".to_string(),
+            "--key".to_string(),
+            "a.pem".to_string(),
+            "-C".to_string(),
+            "b.pem".to_string(),
+        ];
+        assert!(get_constrained_parser().parse(Vec::from(args)).is_ok());
+    }
+
+    #[test]
+    fn parse_conflicts_with_ignores_defaults() {
+        let args: &[String] = &[
+            "serve".to_string(),
+            "-P".to_string(),
+            "secret".to_string(),
+            "--socket".to_string(),
+            "/run/s".to_string(),
+        ];
+        let (results, _) = get_constrained_parser().parse(Vec::from(args)).unwrap();
+        assert_eq!(results.get_value::<String>("port"), "8080");
+        assert_eq!(results.value_source("port"), Some(ValueSource::Default));
+        let args: &[String] = &[
+            "serve".to_string(),
+            "-P".to_string(),
+            "secret".to_string(),
+            "-p".to_string(),
+            "80".to_string(),
+            "--socket".to_string(),
+            "/run/s".to_string(),
+        ];
+        match get_constrained_parser().parse(Vec::from(args)) {
+            Err(ParseError::MutuallyExclusive { arguments, .. }) => {
+                assert_eq!(arguments, ["-p/--port", "-s/--socket"])
+            }
+            Err(e) => panic!("unexpected error {:?}", e),
+            Ok(_) => panic!("Should not have parsed"),
+        }
+    }
+
+    fn get_layered_constrained_parser(config: &str) -> Parser<StubAction> {
+        Parser::new("server", "runs servers")
+            .with_config_file(config)
+            .add_flag("key", "key", 'k', "use tls")
+            .requires("cert")
+            .add_flag("cert", "cert", 'C', "a certificate is installed")
+            .env("SERVER_CERT")
+            .add_optional("port", "port", 'p', Some("8080"), "tcp port")
+            .env("SERVER_PORT")
+            .conflicts_with("socket")
+            .add_optional("socket", "socket", 's', None, "unix socket")
+            .with_main(stub_main)
+    }
+
+    #[test]
+    fn parse_requires_ignores_flags_turned_off_by_lower_layers() {
+        let dir = TestDir::new("parse_requires_ignores_flags_turned_off_by_lower_layers");
+        let args: &[String] = &["--key".to_string()];
+        let parser = get_layered_constrained_parser("/nonexistent/server.toml")
+            .with_env_lookup(|var| (var == "SERVER_CERT").then(|| "no".to_string()));
+        assert!(matches!(
+            parser.parse(Vec::from(args)),
+            Err(ParseError::MissingDependency { .. })
+        ));
+        let config = dir.write("off.toml", "cert = false\n");
+        let parser = get_layered_constrained_parser(&config).with_env_lookup(|_| None);
+        assert!(matches!(
+            parser.parse(Vec::from(args)),
+            Err(ParseError::MissingDependency { .. })
+        ));
+        let config = dir.write("on.toml", "cert = true\n");
+        let parser = get_layered_constrained_parser(&config).with_env_lookup(|_| None);
+        assert!(parser.parse(Vec::from(args)).is_ok());
+    }
+
+    #[test]
+    fn parse_conflicts_with_ignores_values_from_lower_layers() {
+        let dir = TestDir::new("parse_conflicts_with_ignores_values_from_lower_layers");
+        let args: &[String] = &["--socket".to_string(), "/run/s".to_string()];
+        let (results, _) = get_layered_constrained_parser("/nonexistent/server.toml")
+            .with_env_lookup(|var| (var == "SERVER_PORT").then(|| "80".to_string()))
+            .parse(Vec::from(args))
+            .unwrap();
+        assert_eq!(results.get_value::<String>("socket"), "/run/s");
+        let config = dir.write("port.toml", "port = 80\n");
+        let parser = get_layered_constrained_parser(&config).with_env_lookup(|_| None);
+        assert!(parser.parse(Vec::from(args)).is_ok());
+        let config = dir.write("both.toml", "port = 80\nsocket = \"/run/s\"\n");
+        let parser = get_layered_constrained_parser(&config).with_env_lookup(|_| None);
+        assert!(matches!(
+            parser.parse(Vec::new()),
+            Err(ParseError::MutuallyExclusive { .. })
+        ));
+    }
+
+    #[test]
+    #[should_panic(expected = "requires must directly follow an argument")]
+    fn requires_without_argument_panics() {
+        let _: Parser<StubAction> = Parser::new("test", "doc").requires("other");
     }
 
     #[test]
@@ -531,6 +3379,31 @@ mod test {
         assert_eq!(main(), Err("something went wrong".to_string()));
     }
 
+    #[test]
+    fn run_ancestor_main_for_leaf_inheriting_main() {
+        let args: &[String] = &["compute".to_string(), "stuff".to_string()];
+        let parser: Parser<StubAction> = Parser::new("test", "doc")
+            .with_main(|| Err("root main called".to_string()))
+            .add_action(
+                Parser::new("compute", "compute things")
+                    .inherit_main()
+                    .add_positional("stuff", "stuff"),
+            );
+        let (results, main) = parser.parse(Vec::from(args)).unwrap();
+        assert_eq!(results.get_action(), "compute");
+        assert_eq!(main(), Err("root main called".to_string()));
+    }
+
+    #[test]
+    #[should_panic(expected = "leaf parser 'compute' has no main function")]
+    fn parse_sub_action_without_main_panics() {
+        let args: &[String] = &["compute".to_string(), "stuff".to_string()];
+        let parser: Parser<StubAction> = Parser::new("test", "doc")
+            .with_main(|| Err("root main called".to_string()))
+            .add_action(Parser::new("compute", "compute things").add_positional("stuff", "stuff"));
+        let _ = parser.parse(Vec::from(args));
+    }
+
     #[test]
     #[should_panic(expected = "leaf parser 'test' has no main function")]
     fn parse_leaf_without_main_panics() {
@@ -546,7 +3419,7 @@ mod test {
         let parser: Parser<StubAction> = Parser::new("bare", "a bare leaf").with_main(stub_main);
         match parser.parse(Vec::from(args)) {
             Ok(_) => panic!("Should not have parsed"),
-            Err(msg) => assert_eq!(msg, parser.help()),
+            Err(e) => assert_eq!(e.to_string(), parser.help()),
         }
     }
 
@@ -560,7 +3433,7 @@ mod test {
         );
         match parser.parse(Vec::from(args)) {
             Ok(_) => panic!("Should not have parsed"),
-            Err(msg) => assert_eq!(msg, parser.help()),
+            Err(e) => assert_eq!(e.to_string(), parser.help()),
         }
     }
 
@@ -589,7 +3462,9 @@ mod test {
         let args: &[String] = &["World".to_string()];
         let parser: Parser<ActionWithResults> = Parser::new("greet", "doc")
             .add_positional("name", "who to greet")
-            .with_main(|results: &CmdParsingResults| Ok(results.get_value::<String>("name").clone()));
+            .with_main(|results: &CmdParsingResults| {
+                Ok(results.get_value::<String>("name").clone())
+            });
         let (results, main) = parser.parse(Vec::from(args)).unwrap();
         assert_eq!(main(&results), Ok("World".to_string()));
     }
@@ -647,8 +3522,9 @@ compute               I am da computaaah
 "#;
         match parser.parse(Vec::from(args)) {
             Ok(_) => panic!("Should not have parsed"),
-            Err(msg) => {
-                assert_eq!(msg, expected)
+            Err(e) => {
+                assert!(matches!(e, ParseError::HelpRequested { .. }));
+                assert_eq!(e.to_string(), expected)
             }
         }
     }
@@ -672,10 +3548,103 @@ usage: compute [stuff]
 "#;
         match parser.parse(Vec::from(args)) {
             Ok(_) => panic!("Should not have parsed"),
-            Err(msg) => {
-                println!("{}", msg);
-                assert_eq!(msg, expected)
+            Err(e) => {
+                assert_eq!(e.action_path(), ["test".to_string(), "compute".to_string()]);
+                assert_eq!(e.to_string(), expected)
             }
         }
     }
+
+    #[test]
+    fn generate_completion_walks_the_tree() {
+        let parser: Parser<StubAction> = Parser::new("pkg", "manages packages")
+            .add_flag("verbose", "verbose", 'v', "say more")
+            .add_action(
+                Parser::new("remove", "removes a package")
+                    .alias("rm")
+                    .add_optional("level", "level", 'l', None, "log level")
+                    .choices(&["debug", "info"])
+                    .add_positional("source", "where from")
+                    .choices(&["cache", "disk"])
+                    .with_main(stub_main),
+            );
+        let mut bash = Vec::new();
+        parser.generate_completion(Shell::Bash, &mut bash).unwrap();
+        let bash = String::from_utf8(bash).unwrap();
+        assert!(bash.contains("'pkg:remove'|'pkg:rm') level='pkg remove' ;;"));
+        assert!(bash.contains("for word in 'remove' 'rm' '--verbose' '-v'; do"));
+        assert!(bash.contains("for word in '--level' '-l' 'cache' 'disk'; do"));
+        assert!(bash.contains("'pkg remove:--level'|'pkg remove:-l') skip=1 ;;"));
+        let mut fish = Vec::new();
+        parser.generate_completion(Shell::Fish, &mut fish).unwrap();
+        let fish = String::from_utf8(fish).unwrap();
+        assert!(fish.contains("-s l -l level -x -a '\\'debug\\' \\'info\\'' -d 'log level'"));
+        assert!(fish.contains("-a 'disk' -d 'where from'"));
+    }
+
+    fn list_services(_partial: &str) -> Vec<String> {
+        vec!["api".to_string(), "auth".to_string(), "web".to_string()]
+    }
+
+    fn get_completing_parser() -> Parser<StubAction> {
+        Parser::new("ctl", "controls services")
+            .add_flag("verbose", "verbose", 'v', "say more")
+            .add_action(
+                Parser::new("restart", "restarts a service")
+                    .alias("rs")
+                    .add_optional("level", "level", 'l', None, "log level")
+                    .choices(&["debug", "info"])
+                    .add_optional("units", "units", 'u', None, "unit files")
+                    .nargs(Nargs::OneOrMore)
+                    .choices(&["a.service", "b.service"])
+                    .add_optional("color", "color", 'c', Some("never"), "when to color")
+                    .nargs(Nargs::Optional)
+                    .const_value("always")
+                    .choices(&["always", "never"])
+                    .add_positional("service", "the service")
+                    .completer(list_services)
+                    .with_main(stub_main),
+            )
+    }
+
+    fn completions(words: &[&str]) -> String {
+        let words: Vec<String> = words.iter().map(|word| word.to_string()).collect();
+        let mut out = Vec::new();
+        get_completing_parser().complete(&words, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn complete_finds_the_argument_being_completed() {
+        assert_eq!(
+            completions(&[""]),
+            "restart\trestarts a service\nrs\trestarts a service\n"
+        );
+        assert_eq!(completions(&["-"]), "--verbose\tsay more\n-v\tsay more\n");
+        assert_eq!(completions(&["-v", "rs", "a"]), "api\nauth\n");
+        assert_eq!(completions(&["restart", "--level", ""]), "debug\ninfo\n");
+        assert_eq!(completions(&["restart", "-l", "debug", "w"]), "web\n");
+        assert_eq!(completions(&["restart", "--level=d"]), "--level=debug\n");
+        assert_eq!(completions(&["restart", "api", ""]), "");
+    }
+
+    #[test]
+    fn complete_further_values_of_variadic_and_optional_values() {
+        assert_eq!(
+            completions(&["restart", "--units", "a.service", "b"]),
+            "b.service\n"
+        );
+        assert_eq!(
+            completions(&["restart", "-u", "a.service", "-c"]),
+            "-c\twhen to color\n"
+        );
+        assert_eq!(completions(&["restart", "--color", ""]), "always\nnever\n");
+        assert_eq!(completions(&["restart", "--color", "never", "w"]), "web\n");
+    }
+
+    #[test]
+    fn complete_value_attached_to_short_switch() {
+        assert_eq!(completions(&["restart", "-lde"]), "-ldebug\n");
+        assert_eq!(completions(&["restart", "-cn"]), "-cnever\n");
+    }
 }
@@ -0,0 +1,79 @@
+use crate::command_line_parsing_results::CmdParsingResults;
+
+/// Optionals and flags of which at most one may be given, or exactly one if
+/// the group is required.
+pub(crate) struct MutuallyExclusiveGroup {
+    members: Vec<String>,
+    required: bool,
+}
+
+impl MutuallyExclusiveGroup {
+    pub(crate) fn new(members: Vec<String>, required: bool) -> MutuallyExclusiveGroup {
+        MutuallyExclusiveGroup { members, required }
+    }
+
+    /// The names of the member arguments, optionals before flags.
+    pub(crate) fn members(&self) -> &[String] {
+        &self.members
+    }
+
+    pub(crate) fn is_required(&self) -> bool {
+        self.required
+    }
+
+    pub(crate) fn contains(&self, name: &str) -> bool {
+        self.members.iter().any(|member| member == name)
+    }
+
+    /// The members given on the command line, in the environment or in a
+    /// config file, flags turned off there excluded.
+    pub(crate) fn given<'a>(&'a self, result: &CmdParsingResults) -> Vec<&'a str> {
+        self.members
+            .iter()
+            .filter(|member| result.is_given(member))
+            .map(|member| member.as_str())
+            .collect()
+    }
+
+    /// The given members set by a lower layer than another given member,
+    /// e.g. by a config file while another is on the command line. They are
+    /// overridden rather than in conflict.
+    pub(crate) fn overridden<'a>(&'a self, result: &CmdParsingResults) -> Vec<&'a str> {
+        let given = self.given(result);
+        let highest = given.iter().filter_map(|m| result.value_source(m)).min();
+        given
+            .into_iter()
+            .filter(|member| result.value_source(member) > highest)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::command_line_parsing_results::ValueSource;
+
+    #[test]
+    fn given_lists_members_present_in_results() {
+        let group =
+            MutuallyExclusiveGroup::new(vec!["json".to_string(), "yaml".to_string()], false);
+        let mut result = CmdParsingResults::new();
+        assert!(group.given(&result).is_empty());
+        result.add_typed_result_value("yaml".to_string(), Box::new(true), None);
+        result.add_typed_result_value("other".to_string(), Box::new(true), None);
+        assert_eq!(group.given(&result), ["yaml"]);
+    }
+
+    #[test]
+    fn lower_layers_are_overridden() {
+        let group =
+            MutuallyExclusiveGroup::new(vec!["json".to_string(), "yaml".to_string()], false);
+        let mut result = CmdParsingResults::new();
+        result.add_typed_result_value("json".to_string(), Box::new(true), None);
+        result.set_value_source("json", ValueSource::ConfigFile);
+        result.add_typed_result_value("yaml".to_string(), Box::new(true), None);
+        assert_eq!(group.overridden(&result), ["json"]);
+        result.set_value_source("yaml", ValueSource::ConfigFile);
+        assert!(group.overridden(&result).is_empty());
+    }
+}
@@ -0,0 +1,122 @@
+/// How many command line tokens an argument consumes, after argparse's
+/// `nargs`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Nargs {
+    /// Exactly this many tokens. `Exactly(1)` is the default and stores a
+    /// single value, any other count stores a list.
+    Exactly(usize),
+    /// One token if there is one left (`?`), stored as a single value.
+    Optional,
+    /// Any number of tokens (`*`), stored as a list.
+    ZeroOrMore,
+    /// At least one token (`+`), stored as a list.
+    OneOrMore,
+}
+
+impl Nargs {
+    pub(crate) fn min(&self) -> usize {
+        match self {
+            Nargs::Exactly(count) => *count,
+            Nargs::Optional | Nargs::ZeroOrMore => 0,
+            Nargs::OneOrMore => 1,
+        }
+    }
+
+    /// The most tokens consumed, `None` if there is no limit.
+    pub(crate) fn max(&self) -> Option<usize> {
+        match self {
+            Nargs::Exactly(count) => Some(*count),
+            Nargs::Optional => Some(1),
+            Nargs::ZeroOrMore | Nargs::OneOrMore => None,
+        }
+    }
+
+    /// Whether the consumed tokens are stored as a list.
+    pub(crate) fn is_list(&self) -> bool {
+        !matches!(self, Nargs::Exactly(1) | Nargs::Optional)
+    }
+
+    /// The marker appended to an argument's name in usage and help.
+    pub(crate) fn suffix(&self) -> String {
+        match self {
+            Nargs::Exactly(1) => String::new(),
+            Nargs::Exactly(count) => format!("{{{}}}", count),
+            Nargs::Optional => "?".to_string(),
+            Nargs::ZeroOrMore => "*".to_string(),
+            Nargs::OneOrMore => "+".to_string(),
+        }
+    }
+}
+
+impl Default for Nargs {
+    fn default() -> Nargs {
+        Nargs::Exactly(1)
+    }
+}
+
+/// Splits `available` tokens among arguments with the given `nargs`, in
+/// order: each takes as many as it can while leaving enough for the minimum
+/// of the ones after it. Fails with the index of the first argument whose
+/// minimum cannot be met.
+pub(crate) fn distribute(nargs: &[Nargs], available: usize) -> Result<Vec<usize>, usize> {
+    let required: usize = nargs.iter().map(|n| n.min()).sum();
+    if required > available {
+        let mut left = available;
+        for (index, current) in nargs.iter().enumerate() {
+            if current.min() > left {
+                return Err(index);
+            }
+            left -= current.min();
+        }
+    }
+    let mut counts = Vec::new();
+    let mut left = available;
+    for (index, current) in nargs.iter().enumerate() {
+        let reserved: usize = nargs[index + 1..].iter().map(|n| n.min()).sum();
+        let possible = left - reserved;
+        let count = match current.max() {
+            Some(max) => max.min(possible),
+            None => possible,
+        };
+        counts.push(count);
+        left -= count;
+    }
+    Ok(counts)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn distribute_leaves_room_for_trailing_positional() {
+        // cp SRC... DST
+        assert_eq!(
+            distribute(&[Nargs::OneOrMore, Nargs::Exactly(1)], 4),
+            Ok(vec![3, 1])
+        );
+    }
+
+    #[test]
+    fn distribute_fills_optional_only_with_spare_tokens() {
+        let nargs = [Nargs::Optional, Nargs::Exactly(1)];
+        assert_eq!(distribute(&nargs, 1), Ok(vec![0, 1]));
+        assert_eq!(distribute(&nargs, 2), Ok(vec![1, 1]));
+    }
+
+    #[test]
+    fn distribute_fails_below_minimum() {
+        assert_eq!(
+            distribute(&[Nargs::Exactly(2), Nargs::OneOrMore], 2),
+            Err(1)
+        );
+    }
+
+    #[test]
+    fn distribute_leaves_surplus_unassigned() {
+        assert_eq!(
+            distribute(&[Nargs::Exactly(1), Nargs::Optional], 3),
+            Ok(vec![1, 1])
+        );
+    }
+}
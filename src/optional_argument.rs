@@ -1,4 +1,11 @@
-use crate::command_line_parsing_results::CmdParsingResults;
+use crate::choices::Choices;
+use crate::command_line_parsing_results::{CmdParsingResults, ValueSource};
+use crate::config_file::ConfigValue;
+use crate::env_suffix;
+use crate::nargs::Nargs;
+use crate::parse_error::ParseError;
+use crate::token::Token;
+use crate::{Completer, EnvLookup, ValueParser};
 use std::any::Any;
 
 pub(crate) struct OptionalArgument {
@@ -6,8 +13,17 @@ pub(crate) struct OptionalArgument {
     long: String,
     short: char,
     default: Option<String>,
-    parser: fn(&String) -> Box<dyn Any>,
+    parser: ValueParser,
     doc: String,
+    type_name: Option<&'static str>,
+    append: bool,
+    nargs: Nargs,
+    const_value: Option<String>,
+    required: bool,
+    choices: Choices,
+    completer: Option<Completer>,
+    env: Option<String>,
+    config_file: bool,
 }
 
 impl OptionalArgument {
@@ -16,7 +32,7 @@ impl OptionalArgument {
         long: String,
         short: char,
         default: Option<String>,
-        parser: fn(&String) -> Box<dyn Any>,
+        parser: ValueParser,
         doc: String,
     ) -> OptionalArgument {
         OptionalArgument {
@@ -26,68 +42,300 @@ impl OptionalArgument {
             default,
             parser,
             doc,
+            type_name: None,
+            append: false,
+            nargs: Nargs::default(),
+            const_value: None,
+            required: false,
+            choices: Choices::default(),
+            completer: None,
+            env: None,
+            config_file: false,
         }
     }
 
-    pub(crate) fn short(&self) -> char {
-        self.short
+    /// Records the Rust type produced by the parser, for type mismatch errors.
+    pub(crate) fn set_type_name(&mut self, type_name: &'static str) {
+        self.type_name = Some(type_name);
     }
 
-    pub(crate) fn name(&self) -> String {
-        self.name.clone()
+    /// Collects every occurrence into a list instead of keeping the last one.
+    pub(crate) fn set_append(&mut self) {
+        self.append = true;
+    }
+
+    pub(crate) fn set_nargs(&mut self, nargs: Nargs) {
+        self.nargs = nargs;
+    }
+
+    /// The value stored when the switch is given without one, for
+    /// [`Nargs::Optional`].
+    pub(crate) fn set_const_value(&mut self, const_value: String) {
+        self.const_value = Some(const_value);
+    }
+
+    /// Whether the value is optional without one to store for a bare switch.
+    pub(crate) fn lacks_const_value(&self) -> bool {
+        self.nargs == Nargs::Optional && self.const_value.is_none()
+    }
+
+    /// Fails the parse when the switch is not given, its default is unused.
+    pub(crate) fn set_required(&mut self) {
+        self.required = true;
+    }
+
+    /// Restricts the accepted values, checked before conversion.
+    pub(crate) fn set_choices(&mut self, choices: Choices) {
+        self.choices = choices;
+    }
+
+    /// Reads the value from the environment variable `var` when the switch
+    /// is not given, before falling back to the default.
+    pub(crate) fn set_env(&mut self, var: String) {
+        self.env = Some(var);
+    }
+
+    /// Makes the value name a config file to read instead of being filled in
+    /// from one.
+    pub(crate) fn set_config_file(&mut self) {
+        self.config_file = true;
+    }
+
+    pub(crate) fn is_config_file(&self) -> bool {
+        self.config_file
+    }
+
+    pub(crate) fn choices(&self) -> &Choices {
+        &self.choices
+    }
+
+    /// Lists the candidates for a partially typed value of this optional.
+    pub(crate) fn set_completer(&mut self, completer: Completer) {
+        self.completer = Some(completer);
+    }
+
+    pub(crate) fn completer(&self) -> Option<Completer> {
+        self.completer
     }
 
-    pub(crate) fn default(&self) -> Option<String> {
-        self.default.clone()
+    pub(crate) fn doc(&self) -> &str {
+        &self.doc
+    }
+
+    pub(crate) fn is_required(&self) -> bool {
+        self.required
+    }
+
+    pub(crate) fn long(&self) -> &str {
+        self.long.as_str()
+    }
+
+    pub(crate) fn short(&self) -> char {
+        self.short
     }
 
-    pub(crate) fn parser(&self) -> fn(&String) -> Box<dyn Any> {
-        self.parser
+    pub(crate) fn name(&self) -> String {
+        self.name.clone()
     }
 
     pub(crate) fn help(&self) -> String {
-        let name = format!("-{},--{}", self.short, self.long);
-        let spaces = 22 - name.len();
+        let name = format!(
+            "-{},--{}{}{}",
+            self.short,
+            self.long,
+            self.nargs.suffix(),
+            self.choices.suffix()
+        );
+        let spaces = 22_usize.saturating_sub(name.len()).max(1);
         name + String::from_utf8(vec![b' '; spaces])
             .expect("should be a string of whitespaces")
             .as_str()
             + &self.doc
+            + &env_suffix(&self.env)
+            + if self.required { " (required)" } else { "" }
+    }
+
+    /// Stores the default of an optional that was not given: split on
+    /// whitespace for list nargs, an empty list for appending optionals
+    /// without one.
+    pub(crate) fn store_default(&self, result: &mut CmdParsingResults) -> Result<(), ParseError> {
+        match &self.default {
+            Some(default) => self.store_text(result, default, ValueSource::Default),
+            None if self.append => {
+                self.store(result, Vec::new());
+                result.set_value_source(&self.name, ValueSource::Default);
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Stores the value of the environment variable of an optional that was
+    /// not given, if `lookup` finds it, like a default.
+    pub(crate) fn store_environment(
+        &self,
+        result: &mut CmdParsingResults,
+        lookup: EnvLookup,
+    ) -> Result<(), ParseError> {
+        match self.env.as_deref().and_then(lookup) {
+            Some(value) => self.store_text(result, &value, ValueSource::Environment),
+            None => Ok(()),
+        }
+    }
+
+    /// Stores a value from a config file of an optional that was not given:
+    /// a string like a default, an array one token per element.
+    pub(crate) fn store_config(
+        &self,
+        result: &mut CmdParsingResults,
+        value: &ConfigValue,
+    ) -> Result<(), ParseError> {
+        match value {
+            ConfigValue::Scalar(text) => self.store_text(result, text, ValueSource::ConfigFile),
+            ConfigValue::List(items) => {
+                let tokens: Vec<&str> = items.iter().map(|item| item.as_str()).collect();
+                self.store_tokens(result, &tokens, ValueSource::ConfigFile)
+            }
+        }
     }
 
+    /// Stores a value that did not come from the command line, split on
+    /// whitespace for list nargs.
+    fn store_text(
+        &self,
+        result: &mut CmdParsingResults,
+        value: &str,
+        source: ValueSource,
+    ) -> Result<(), ParseError> {
+        let tokens: Vec<&str> = if self.nargs.is_list() {
+            value.split_whitespace().collect()
+        } else {
+            vec![value]
+        };
+        self.store_tokens(result, &tokens, source)
+    }
+
+    fn store_tokens(
+        &self,
+        result: &mut CmdParsingResults,
+        tokens: &[&str],
+        source: ValueSource,
+    ) -> Result<(), ParseError> {
+        if !self.nargs.is_list() && !self.append && tokens.len() != 1 {
+            return Err(ParseError::ConversionFailed {
+                argument: self.name.clone(),
+                value: tokens.join(" "),
+                reason: "expected a single value".to_string(),
+                position: None,
+                context: Box::default(),
+            });
+        }
+        let mut values = Vec::new();
+        for token in tokens {
+            values.push(self.convert(&token.to_string(), None)?);
+        }
+        self.store(result, values);
+        result.set_value_source(&self.name, source);
+        Ok(())
+    }
+
+    /// Consumes the switch at the front of `cmdline` and its values: a value
+    /// attached as in `--name=value` or `-nvalue` comes first, then exactly
+    /// the missing tokens for [`Nargs::Exactly`], none of which may be a
+    /// switch or `--`, otherwise the tokens up to the next switch. A bare
+    /// [`Nargs::Optional`] switch stores its const value.
     pub(crate) fn parse<'b>(
         &self,
         result: &mut CmdParsingResults,
         cmdline: &'b [String],
-    ) -> Result<&'b [String], String> {
-        match cmdline.first() {
-            Some(peeked) => {
-                // we need at least two char for a short flag, e.g., -f
-                if peeked.len() >= 2 {
-                    // if the name matches
-                    if peeked[2..] == self.long
-                        || peeked[1..]
-                            .chars()
-                            .next()
-                            .expect("there needs to be a char")
-                            == self.short
-                    {
-                        return match cmdline[1..].first() {
-                            // store the value
-                            Some(value) => {
-                                result.add_result_value(
-                                    self.name.clone(),
-                                    (self.parser)(&value.clone()),
-                                );
-                                Ok(&cmdline[2..])
-                            }
-                            // if there is no value panic
-                            None => Err(format!("unexpected eol after {}", self.name)),
-                        };
-                    }
-                }
-                Ok(cmdline)
+    ) -> Result<&'b [String], ParseError> {
+        let token = match cmdline.first() {
+            Some(peeked) => Token::classify(peeked),
+            None => return Ok(cmdline),
+        };
+        if !token.matches(&self.long, self.short) {
+            return Ok(cmdline);
+        }
+        let attached = token.attached_value();
+        let following = &cmdline[1..];
+        let count = self.count_values(following, attached.is_some())?;
+        let mut values = Vec::new();
+        if let Some(value) = attached {
+            values.push(self.convert(&value.to_string(), Some(0))?);
+        }
+        for (index, value) in following[..count].iter().enumerate() {
+            values.push(self.convert(value, Some(index + 1))?);
+        }
+        if values.is_empty()
+            && let Some(const_value) = &self.const_value
+        {
+            values.push(self.convert(const_value, Some(0))?);
+        }
+        self.store(result, values);
+        Ok(&following[count..])
+    }
+
+    /// Whether the switch can take another value after `given` ones.
+    pub(crate) fn takes_another_value(&self, given: usize) -> bool {
+        self.nargs.max().is_none_or(|max| given < max)
+    }
+
+    /// How many of the tokens after the switch are its values, given whether
+    /// one value was attached to the switch.
+    pub(crate) fn count_values(
+        &self,
+        following: &[String],
+        attached: bool,
+    ) -> Result<usize, ParseError> {
+        let available = following
+            .iter()
+            .take_while(|token| Token::classify(token).is_value())
+            .count();
+        let (count, enough) = match (self.nargs, attached) {
+            (Nargs::Exactly(count), _) => {
+                let missing = count.saturating_sub(usize::from(attached));
+                (missing, available >= missing)
             }
-            None => Ok(cmdline),
+            (Nargs::Optional, true) => (0, true),
+            (Nargs::Optional, false) => (available.min(1), true),
+            (Nargs::ZeroOrMore, _) => (available, true),
+            (Nargs::OneOrMore, _) => (available, attached || available >= 1),
+        };
+        if enough {
+            Ok(count)
+        } else {
+            // if there is no value report it
+            Err(ParseError::MissingValue {
+                argument: self.name.clone(),
+                position: 0,
+                context: Box::default(),
+            })
+        }
+    }
+
+    fn convert(&self, value: &String, position: Option<usize>) -> Result<Box<dyn Any>, ParseError> {
+        self.choices.check(&self.name, value, position)?;
+        (self.parser)(value).map_err(|reason| ParseError::ConversionFailed {
+            argument: self.name.clone(),
+            value: value.clone(),
+            reason,
+            position,
+            context: Box::default(),
+        })
+    }
+
+    fn store(&self, result: &mut CmdParsingResults, values: Vec<Box<dyn Any>>) {
+        if self.append {
+            for value in values {
+                result.append_result_value(self.name.clone(), value, self.type_name);
+            }
+            if !result.keys().contains(&&self.name) {
+                result.add_result_values(self.name.clone(), Vec::new(), self.type_name);
+            }
+        } else if self.nargs.is_list() {
+            result.add_result_values(self.name.clone(), values, self.type_name);
+        } else if let Some(value) = values.into_iter().next() {
+            result.add_typed_result_value(self.name.clone(), value, self.type_name);
         }
     }
 }
@@ -102,7 +350,7 @@ mod test {
             "test".to_string(),
             't',
             None,
-            |val| Box::new(val.clone()),
+            |val| Ok(Box::new(val.clone())),
             "test optional".to_string(),
         )
     }
@@ -113,7 +361,7 @@ mod test {
             "test".to_string(),
             't',
             None,
-            |val| Box::new(val.parse::<i32>().expect("there should be a number")),
+            |val| Ok(Box::new(val.parse::<i32>().map_err(|e| e.to_string())?)),
             "test optional".to_string(),
         )
     }
@@ -127,9 +375,11 @@ mod test {
         ];
         let mut result: CmdParsingResults = CmdParsingResults::new();
         let optional: OptionalArgument = get_string_optional();
-        let remaining = optional.parse(&mut result, cmdline)?;
+        let remaining = optional
+            .parse(&mut result, cmdline)
+            .map_err(|e| e.to_string())?;
         if remaining == ["chaff".to_string()] {
-            if result.get_value::<String>(&*optional.name) == "value" {
+            if result.get_value::<String>(&optional.name) == "value" {
                 Ok(())
             } else {
                 Err("did not extract value as value".to_string())
@@ -144,9 +394,11 @@ mod test {
         let cmdline: &[String] = &["-t".to_string(), "value".to_string(), "chaff".to_string()];
         let mut result: CmdParsingResults = CmdParsingResults::new();
         let optional: OptionalArgument = get_string_optional();
-        let remaining = optional.parse(&mut result, cmdline)?;
+        let remaining = optional
+            .parse(&mut result, cmdline)
+            .map_err(|e| e.to_string())?;
         if remaining == ["chaff".to_string()] {
-            if result.get_value::<String>(&*optional.name) == "value" {
+            if result.get_value::<String>(&optional.name) == "value" {
                 Ok(())
             } else {
                 Err("did not extract value as value".to_string())
@@ -164,9 +416,11 @@ mod test {
         let cmdline: &[String] = &["-t".to_string(), "42".to_string(), "chaff".to_string()];
         let mut result: CmdParsingResults = CmdParsingResults::new();
         let optional: OptionalArgument = get_i32_optional();
-        let remaining = optional.parse(&mut result, cmdline)?;
+        let remaining = optional
+            .parse(&mut result, cmdline)
+            .map_err(|e| e.to_string())?;
         if remaining == ["chaff".to_string()] {
-            if *result.get_value::<i32>(&*optional.name) == 42 {
+            if *result.get_value::<i32>(&optional.name) == 42 {
                 Ok(())
             } else {
                 Err("did not extract 42 as value".to_string())
@@ -180,14 +434,40 @@ mod test {
     }
 
     #[test]
-    #[should_panic(expected = "there should be a number")]
     fn parse_optional_argument_converted_bad() {
         let cmdline: &[String] = &["-t".to_string(), "test".to_string(), "chaff".to_string()];
         let mut result: CmdParsingResults = CmdParsingResults::new();
         let optional: OptionalArgument = get_i32_optional();
-        optional
-            .parse(&mut result, cmdline)
-            .expect("expect a panic message");
+        match optional.parse(&mut result, cmdline) {
+            Err(ParseError::ConversionFailed {
+                argument,
+                value,
+                position,
+                ..
+            }) => {
+                assert_eq!(argument, "test");
+                assert_eq!(value, "test");
+                assert_eq!(position, Some(1));
+            }
+            Err(e) => panic!("unexpected error {:?}", e),
+            Ok(_) => panic!("should not have parsed"),
+        }
+    }
+
+    #[test]
+    fn parse_appending_optional_argument_twice() {
+        let cmdline: &[String] = &[
+            "-t".to_string(),
+            "a".to_string(),
+            "-t".to_string(),
+            "b".to_string(),
+        ];
+        let mut result: CmdParsingResults = CmdParsingResults::new();
+        let mut optional: OptionalArgument = get_string_optional();
+        optional.set_append();
+        let remaining = optional.parse(&mut result, cmdline).unwrap();
+        optional.parse(&mut result, remaining).unwrap();
+        assert_eq!(result.get_values::<String>("test"), ["a", "b"]);
     }
 
     #[test]
@@ -203,4 +483,123 @@ mod test {
         let optional: OptionalArgument = get_string_optional();
         assert_eq!(optional.help(), "-t,--test             test optional")
     }
+
+    fn args(tokens: &[&str]) -> Vec<String> {
+        tokens.iter().map(|token| token.to_string()).collect()
+    }
+
+    #[test]
+    fn parse_fixed_count_optional() {
+        let mut optional = get_i32_optional();
+        optional.set_nargs(Nargs::Exactly(2));
+        let mut result: CmdParsingResults = CmdParsingResults::new();
+        let cmdline = args(&["--test", "1", "10", "chaff"]);
+        let remaining = optional.parse(&mut result, &cmdline).unwrap();
+        assert_eq!(remaining, ["chaff".to_string()]);
+        assert_eq!(result.get_array::<i32, 2>("test"), [&1, &10]);
+    }
+
+    #[test]
+    fn parse_fixed_count_optional_missing_value() {
+        let mut optional = get_i32_optional();
+        optional.set_nargs(Nargs::Exactly(2));
+        let mut result: CmdParsingResults = CmdParsingResults::new();
+        assert!(matches!(
+            optional.parse(&mut result, &args(&["-t", "1"])),
+            Err(ParseError::MissingValue { position: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn parse_variadic_optional_stops_at_next_switch() {
+        let mut optional = get_string_optional();
+        optional.set_nargs(Nargs::OneOrMore);
+        let mut result: CmdParsingResults = CmdParsingResults::new();
+        let cmdline = args(&["-t", "a", "b", "-f"]);
+        let remaining = optional.parse(&mut result, &cmdline).unwrap();
+        assert_eq!(remaining, ["-f".to_string()]);
+        assert_eq!(result.get_values::<String>("test").len(), 2);
+        assert!(matches!(
+            optional.parse(&mut result, &args(&["-t", "-f"])),
+            Err(ParseError::MissingValue { .. })
+        ));
+    }
+
+    #[test]
+    fn parse_optional_value_falls_back_to_const() {
+        let mut optional = get_string_optional();
+        optional.set_nargs(Nargs::Optional);
+        optional.set_const_value("always".to_string());
+        let mut result: CmdParsingResults = CmdParsingResults::new();
+        let cmdline = args(&["-t", "-f"]);
+        let remaining = optional.parse(&mut result, &cmdline).unwrap();
+        assert_eq!(remaining, ["-f".to_string()]);
+        assert_eq!(result.get_value::<String>("test"), "always");
+        optional
+            .parse(&mut result, &args(&["-t", "never"]))
+            .unwrap();
+        assert_eq!(result.get_value::<String>("test"), "never");
+    }
+
+    #[test]
+    fn list_default_is_split_on_whitespace() {
+        let mut optional = OptionalArgument::new(
+            "test".to_string(),
+            "test".to_string(),
+            't',
+            Some("1 10".to_string()),
+            |val| Ok(Box::new(val.parse::<i32>().map_err(|e| e.to_string())?)),
+            "test optional".to_string(),
+        );
+        optional.set_nargs(Nargs::Exactly(2));
+        let mut result: CmdParsingResults = CmdParsingResults::new();
+        optional.store_default(&mut result).unwrap();
+        assert_eq!(result.get_array::<i32, 2>("test"), [&1, &10]);
+    }
+
+    #[test]
+    fn parse_optional_attached_values() {
+        let optional = get_i32_optional();
+        let mut result: CmdParsingResults = CmdParsingResults::new();
+        let cmdline = args(&["--test=30", "chaff"]);
+        let remaining = optional.parse(&mut result, &cmdline).unwrap();
+        assert_eq!(remaining, ["chaff".to_string()]);
+        assert_eq!(*result.get_value::<i32>("test"), 30);
+        let cmdline = args(&["-t42"]);
+        let remaining = optional.parse(&mut result, &cmdline).unwrap();
+        assert!(remaining.is_empty());
+        assert_eq!(*result.get_value::<i32>("test"), 42);
+    }
+
+    #[test]
+    fn parse_optional_attached_value_counts_towards_nargs() {
+        let mut optional = get_i32_optional();
+        optional.set_nargs(Nargs::Exactly(2));
+        let mut result: CmdParsingResults = CmdParsingResults::new();
+        let cmdline = args(&["--test=1", "10", "chaff"]);
+        let remaining = optional.parse(&mut result, &cmdline).unwrap();
+        assert_eq!(remaining, ["chaff".to_string()]);
+        assert_eq!(result.get_array::<i32, 2>("test"), [&1, &10]);
+    }
+
+    #[test]
+    fn parse_optional_bad_attached_value_points_at_switch() {
+        let optional = get_i32_optional();
+        let mut result: CmdParsingResults = CmdParsingResults::new();
+        assert!(matches!(
+            optional.parse(&mut result, &args(&["--test=ten"])),
+            Err(ParseError::ConversionFailed {
+                position: Some(0),
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn parse_optional_ignores_other_long_names() {
+        let optional = get_string_optional();
+        let mut result: CmdParsingResults = CmdParsingResults::new();
+        let cmdline = args(&["--tester", "value"]);
+        assert_eq!(optional.parse(&mut result, &cmdline).unwrap(), cmdline);
+    }
 }
@@ -0,0 +1,447 @@
+use std::fmt;
+
+/// Where in the parser tree an error occurred.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ErrorContext {
+    /// The root parser's name followed by each sub-action taken.
+    pub action_path: Vec<String>,
+    /// The help text of the parser that failed.
+    pub help: String,
+}
+
+impl ErrorContext {
+    pub(crate) fn new(action_path: &[String], help: String) -> Box<ErrorContext> {
+        Box::new(ErrorContext {
+            action_path: action_path.to_vec(),
+            help,
+        })
+    }
+}
+
+/// The reason a command line could not be parsed.
+///
+/// Every variant carries the [`ErrorContext`] of the parser that failed.
+/// Positions are indices into the command line handed to
+/// [`Parser::parse`](crate::Parser::parse). `Display` renders the message
+/// followed by the help text, or only the help text for `HelpRequested`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseError {
+    /// `--help` or `-h` was given.
+    HelpRequested { context: Box<ErrorContext> },
+    /// The command line ended before all positionals were filled.
+    MissingPositional {
+        argument: String,
+        context: Box<ErrorContext>,
+    },
+    /// Required optionals were not given; `arguments` lists every one of
+    /// their switches.
+    MissingRequired {
+        arguments: Vec<String>,
+        context: Box<ErrorContext>,
+    },
+    /// Several arguments of a mutually exclusive group were given; `arguments`
+    /// lists their switches.
+    MutuallyExclusive {
+        arguments: Vec<String>,
+        context: Box<ErrorContext>,
+    },
+    /// An argument was given without another one it requires, both named by
+    /// their switches.
+    MissingDependency {
+        argument: String,
+        required: String,
+        context: Box<ErrorContext>,
+    },
+    /// An optional was given as the last token, without its value.
+    MissingValue {
+        argument: String,
+        position: usize,
+        context: Box<ErrorContext>,
+    },
+    /// The parser has sub-actions but none was given.
+    MissingAction {
+        position: usize,
+        context: Box<ErrorContext>,
+    },
+    /// The token in action position does not name a sub-action.
+    /// `suggestion` is the closest action name, if any is a likely typo.
+    UnknownAction {
+        action: String,
+        suggestion: Option<String>,
+        position: usize,
+        context: Box<ErrorContext>,
+    },
+    /// An abbreviated action name is the prefix of several actions.
+    AmbiguousAction {
+        action: String,
+        candidates: Vec<String>,
+        position: usize,
+        context: Box<ErrorContext>,
+    },
+    /// A switch that names none of the parser's optionals or flags.
+    /// `suggestion` is the closest long switch, if any is a likely typo.
+    UnknownOption {
+        option: String,
+        suggestion: Option<String>,
+        position: usize,
+        context: Box<ErrorContext>,
+    },
+    /// An abbreviated `--switch` is the prefix of several long names.
+    AmbiguousOption {
+        option: String,
+        candidates: Vec<String>,
+        position: usize,
+        context: Box<ErrorContext>,
+    },
+    /// Tokens were left over after everything else was matched.
+    UnexpectedArguments {
+        arguments: Vec<String>,
+        position: usize,
+        context: Box<ErrorContext>,
+    },
+    /// A value was attached to a flag, as in `--verbose=yes`.
+    UnexpectedValue {
+        argument: String,
+        value: String,
+        position: usize,
+        context: Box<ErrorContext>,
+    },
+    /// A value converter rejected the given value. `position` is `None` when
+    /// the value did not come from the command line, e.g. a declared default.
+    ConversionFailed {
+        argument: String,
+        value: String,
+        reason: String,
+        position: Option<usize>,
+        context: Box<ErrorContext>,
+    },
+    /// A value is not one of the argument's `choices`. `position` is `None`
+    /// as for `ConversionFailed`.
+    InvalidChoice {
+        argument: String,
+        value: String,
+        choices: Vec<String>,
+        position: Option<usize>,
+        context: Box<ErrorContext>,
+    },
+    /// A config file could not be read, is malformed or names an unknown
+    /// option or action. `line` counts from one, `None` if the whole file
+    /// failed.
+    ConfigFailed {
+        path: String,
+        line: Option<usize>,
+        reason: String,
+        context: Box<ErrorContext>,
+    },
+    /// A response file named by the token at `position` could not be
+    /// expanded; `path` is the failing file, which may be a nested one.
+    FromFileFailed {
+        path: String,
+        reason: String,
+        position: usize,
+        context: Box<ErrorContext>,
+    },
+}
+
+impl ParseError {
+    /// The name of the argument or action the error is about, if any.
+    pub fn argument(&self) -> Option<&str> {
+        match self {
+            ParseError::MissingPositional { argument, .. }
+            | ParseError::MissingDependency { argument, .. }
+            | ParseError::MissingValue { argument, .. }
+            | ParseError::UnexpectedValue { argument, .. }
+            | ParseError::ConversionFailed { argument, .. }
+            | ParseError::InvalidChoice { argument, .. } => Some(argument.as_str()),
+            ParseError::UnknownAction { action, .. }
+            | ParseError::AmbiguousAction { action, .. } => Some(action.as_str()),
+            ParseError::UnknownOption { option, .. }
+            | ParseError::AmbiguousOption { option, .. } => Some(option.as_str()),
+            ParseError::HelpRequested { .. }
+            | ParseError::MissingRequired { .. }
+            | ParseError::MutuallyExclusive { .. }
+            | ParseError::MissingAction { .. }
+            | ParseError::UnexpectedArguments { .. }
+            | ParseError::ConfigFailed { .. }
+            | ParseError::FromFileFailed { .. } => None,
+        }
+    }
+
+    /// The index of the offending token in the parsed command line, if any.
+    pub fn position(&self) -> Option<usize> {
+        match self {
+            ParseError::MissingValue { position, .. }
+            | ParseError::MissingAction { position, .. }
+            | ParseError::UnknownAction { position, .. }
+            | ParseError::AmbiguousAction { position, .. }
+            | ParseError::UnknownOption { position, .. }
+            | ParseError::AmbiguousOption { position, .. }
+            | ParseError::UnexpectedArguments { position, .. }
+            | ParseError::UnexpectedValue { position, .. }
+            | ParseError::FromFileFailed { position, .. } => Some(*position),
+            ParseError::ConversionFailed { position, .. }
+            | ParseError::InvalidChoice { position, .. } => *position,
+            ParseError::HelpRequested { .. }
+            | ParseError::MissingPositional { .. }
+            | ParseError::MissingRequired { .. }
+            | ParseError::MutuallyExclusive { .. }
+            | ParseError::MissingDependency { .. }
+            | ParseError::ConfigFailed { .. } => None,
+        }
+    }
+
+    /// What the unknown action or option given was probably meant to be.
+    pub fn suggestion(&self) -> Option<&str> {
+        match self {
+            ParseError::UnknownAction { suggestion, .. }
+            | ParseError::UnknownOption { suggestion, .. } => suggestion.as_deref(),
+            _ => None,
+        }
+    }
+
+    pub fn action_path(&self) -> &[String] {
+        &self.context().action_path
+    }
+
+    /// The help text of the parser the error occurred in.
+    pub fn help(&self) -> &str {
+        &self.context().help
+    }
+
+    fn context(&self) -> &ErrorContext {
+        match self {
+            ParseError::HelpRequested { context }
+            | ParseError::MissingPositional { context, .. }
+            | ParseError::MissingRequired { context, .. }
+            | ParseError::MutuallyExclusive { context, .. }
+            | ParseError::MissingDependency { context, .. }
+            | ParseError::MissingValue { context, .. }
+            | ParseError::MissingAction { context, .. }
+            | ParseError::UnknownAction { context, .. }
+            | ParseError::AmbiguousAction { context, .. }
+            | ParseError::UnknownOption { context, .. }
+            | ParseError::AmbiguousOption { context, .. }
+            | ParseError::UnexpectedArguments { context, .. }
+            | ParseError::UnexpectedValue { context, .. }
+            | ParseError::ConversionFailed { context, .. }
+            | ParseError::InvalidChoice { context, .. }
+            | ParseError::ConfigFailed { context, .. }
+            | ParseError::FromFileFailed { context, .. } => context,
+        }
+    }
+
+    /// Places an error raised by a single argument into the parser it belongs
+    /// to: shifts its position by the index the argument's slice started at,
+    /// and fills in the action path and help text.
+    pub(crate) fn in_context(
+        mut self,
+        offset: usize,
+        path: &[String],
+        parser_help: String,
+    ) -> ParseError {
+        match &mut self {
+            ParseError::MissingValue { position, .. }
+            | ParseError::MissingAction { position, .. }
+            | ParseError::UnknownAction { position, .. }
+            | ParseError::AmbiguousAction { position, .. }
+            | ParseError::UnknownOption { position, .. }
+            | ParseError::AmbiguousOption { position, .. }
+            | ParseError::UnexpectedArguments { position, .. }
+            | ParseError::UnexpectedValue { position, .. }
+            | ParseError::FromFileFailed { position, .. }
+            | ParseError::ConversionFailed {
+                position: Some(position),
+                ..
+            }
+            | ParseError::InvalidChoice {
+                position: Some(position),
+                ..
+            } => *position += offset,
+            ParseError::HelpRequested { .. }
+            | ParseError::MissingPositional { .. }
+            | ParseError::MissingRequired { .. }
+            | ParseError::MutuallyExclusive { .. }
+            | ParseError::MissingDependency { .. }
+            | ParseError::ConfigFailed { .. }
+            | ParseError::ConversionFailed { position: None, .. }
+            | ParseError::InvalidChoice { position: None, .. } => {}
+        }
+        match &mut self {
+            ParseError::HelpRequested { context }
+            | ParseError::MissingPositional { context, .. }
+            | ParseError::MissingRequired { context, .. }
+            | ParseError::MutuallyExclusive { context, .. }
+            | ParseError::MissingDependency { context, .. }
+            | ParseError::MissingValue { context, .. }
+            | ParseError::MissingAction { context, .. }
+            | ParseError::UnknownAction { context, .. }
+            | ParseError::AmbiguousAction { context, .. }
+            | ParseError::UnknownOption { context, .. }
+            | ParseError::AmbiguousOption { context, .. }
+            | ParseError::UnexpectedArguments { context, .. }
+            | ParseError::UnexpectedValue { context, .. }
+            | ParseError::ConversionFailed { context, .. }
+            | ParseError::InvalidChoice { context, .. }
+            | ParseError::ConfigFailed { context, .. }
+            | ParseError::FromFileFailed { context, .. } => {
+                *context = ErrorContext::new(path, parser_help)
+            }
+        }
+        self
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let message = match self {
+            ParseError::HelpRequested { context } => return write!(f, "{}", context.help),
+            ParseError::MissingPositional { argument, .. } => {
+                format!("missing required positional argument: {}", argument)
+            }
+            ParseError::MissingRequired { arguments, .. } => format!(
+                "the following arguments are required: {}",
+                arguments.join(", ")
+            ),
+            ParseError::MutuallyExclusive { arguments, .. } => format!(
+                "the arguments {} cannot be used together",
+                arguments.join(", ")
+            ),
+            ParseError::MissingDependency {
+                argument, required, ..
+            } => format!("{} requires {}", argument, required),
+            ParseError::MissingValue { argument, .. } => {
+                format!("missing value after {}", argument)
+            }
+            ParseError::MissingAction { .. } => "You have to choose an action.".to_string(),
+            ParseError::UnknownAction {
+                action, suggestion, ..
+            } => format!("Unknown action {}{}", action, did_you_mean(suggestion)),
+            ParseError::AmbiguousAction {
+                action, candidates, ..
+            } => format!(
+                "Ambiguous action {} could match {}",
+                action,
+                candidates.join(", ")
+            ),
+            ParseError::UnknownOption {
+                option, suggestion, ..
+            } => format!("Unknown option {}{}", option, did_you_mean(suggestion)),
+            ParseError::AmbiguousOption {
+                option, candidates, ..
+            } => format!(
+                "Ambiguous option {} could match {}",
+                option,
+                candidates.join(", ")
+            ),
+            ParseError::UnexpectedArguments { arguments, .. } => {
+                format!("Too many cmd arguments: {:?}", arguments)
+            }
+            ParseError::UnexpectedValue {
+                argument, value, ..
+            } => format!("{} does not take a value, got '{}'", argument, value),
+            ParseError::ConversionFailed {
+                argument,
+                value,
+                reason,
+                ..
+            } => format!("invalid value '{}' for {}: {}", value, argument, reason),
+            ParseError::InvalidChoice {
+                argument,
+                value,
+                choices,
+                ..
+            } => format!(
+                "invalid choice '{}' for {} (choose from {})",
+                value,
+                argument,
+                choices.join(", ")
+            ),
+            ParseError::ConfigFailed {
+                path,
+                line: Some(line),
+                reason,
+                ..
+            } => format!("config file {}, line {}: {}", path, line, reason),
+            ParseError::ConfigFailed {
+                path,
+                line: None,
+                reason,
+                ..
+            } => format!("config file {}: {}", path, reason),
+            ParseError::FromFileFailed { path, reason, .. } => {
+                format!("cannot read arguments from {}: {}", path, reason)
+            }
+        };
+        write!(f, "Bad Cmd Arguments: {}\n\n{}", message, self.help())
+    }
+}
+
+fn did_you_mean(suggestion: &Option<String>) -> String {
+    match suggestion {
+        Some(suggestion) => format!(", did you mean {}?", suggestion),
+        None => String::new(),
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn missing_value() -> ParseError {
+        ParseError::MissingValue {
+            argument: "optional".to_string(),
+            position: 1,
+            context: Box::default(),
+        }
+    }
+
+    #[test]
+    fn in_context_shifts_position_and_sets_path() {
+        let error = missing_value().in_context(
+            3,
+            &["tool".to_string(), "start".to_string()],
+            "start help".to_string(),
+        );
+        assert_eq!(error.position(), Some(4));
+        assert_eq!(
+            error.action_path(),
+            ["tool".to_string(), "start".to_string()]
+        );
+        assert_eq!(error.help(), "start help");
+        assert_eq!(error.argument(), Some("optional"));
+    }
+
+    #[test]
+    fn display_renders_message_and_help() {
+        let error = missing_value().in_context(0, &[], "the help".to_string());
+        assert_eq!(
+            error.to_string(),
+            "Bad Cmd Arguments: missing value after optional\n\nthe help"
+        );
+    }
+
+    #[test]
+    fn display_help_requested_is_only_the_help() {
+        let error = ParseError::HelpRequested {
+            context: ErrorContext::new(&["tool".to_string()], "the help".to_string()),
+        };
+        assert_eq!(error.to_string(), "the help");
+    }
+
+    #[test]
+    fn display_unknown_action_with_suggestion() {
+        let error = ParseError::UnknownAction {
+            action: "strat".to_string(),
+            suggestion: Some("start".to_string()),
+            position: 0,
+            context: ErrorContext::new(&[], "the help".to_string()),
+        };
+        assert_eq!(error.suggestion(), Some("start"));
+        assert_eq!(
+            error.to_string(),
+            "Bad Cmd Arguments: Unknown action strat, did you mean start?\n\nthe help"
+        );
+    }
+}
@@ -1,10 +1,19 @@
-use crate::command_line_parsing_results::CmdParsingResults;
+use crate::choices::Choices;
+use crate::command_line_parsing_results::{CmdParsingResults, ValueSource};
+use crate::nargs::Nargs;
+use crate::parse_error::ParseError;
+use crate::{Completer, ValueParser};
 use std::any::Any;
 
 pub(crate) struct PositionalArgument {
     name: String,
     doc: String,
-    parser: fn(&String) -> Box<dyn Any>,
+    parser: ValueParser,
+    type_name: Option<&'static str>,
+    nargs: Nargs,
+    default: Option<String>,
+    choices: Choices,
+    completer: Option<Completer>,
 }
 
 impl PositionalArgument {
@@ -12,19 +21,71 @@ impl PositionalArgument {
         self.name.as_str()
     }
 
-    pub(crate) fn new(
-        name: String,
-        parser: fn(&String) -> Box<dyn Any>,
-        doc: String,
-    ) -> PositionalArgument {
-        PositionalArgument { name, parser, doc }
+    pub(crate) fn doc(&self) -> &str {
+        &self.doc
+    }
+
+    pub(crate) fn choices(&self) -> &Choices {
+        &self.choices
+    }
+
+    /// Lists the candidates for a partially typed value of this positional.
+    pub(crate) fn set_completer(&mut self, completer: Completer) {
+        self.completer = Some(completer);
+    }
+
+    pub(crate) fn completer(&self) -> Option<Completer> {
+        self.completer
+    }
+
+    pub(crate) fn new(name: String, parser: ValueParser, doc: String) -> PositionalArgument {
+        PositionalArgument {
+            name,
+            parser,
+            doc,
+            type_name: None,
+            nargs: Nargs::default(),
+            default: None,
+            choices: Choices::default(),
+            completer: None,
+        }
+    }
+
+    /// Records the Rust type produced by the parser, for type mismatch errors.
+    pub(crate) fn set_type_name(&mut self, type_name: &'static str) {
+        self.type_name = Some(type_name);
+    }
+
+    pub(crate) fn set_nargs(&mut self, nargs: Nargs) {
+        self.nargs = nargs;
+    }
+
+    pub(crate) fn nargs(&self) -> Nargs {
+        self.nargs
+    }
+
+    /// The value stored when no token is left for this positional.
+    pub(crate) fn set_default(&mut self, default: String) {
+        self.default = Some(default);
+    }
+
+    /// Restricts the accepted tokens, checked before conversion.
+    pub(crate) fn set_choices(&mut self, choices: Choices) {
+        self.choices = choices;
+    }
+
+    /// The name as shown in usage and help, marked with its nargs and
+    /// followed by its choices.
+    pub(crate) fn display_name(&self) -> String {
+        self.name.clone() + &self.nargs.suffix() + &self.choices.suffix()
     }
 
     //todo this is a magic constant that needs to be unified across all help generators
     pub(crate) fn help(&self) -> String {
-        let spaces = 20 - self.name.len();
+        let name = self.display_name();
+        let spaces = 20_usize.saturating_sub(name.len()).max(1);
         let spaced_name: String = "[".to_string()
-            + &self.name
+            + &name
             + "]"
             + String::from_utf8(vec![b' '; spaces])
                 .expect("should be a string of whitespaces")
@@ -32,21 +93,56 @@ impl PositionalArgument {
         spaced_name + &self.doc
     }
 
+    /// Consumes as many tokens as the nargs allow; fewer than its minimum is
+    /// a `MissingPositional`. Without a token, the default (if any) is stored
+    /// and the positional is not recorded as given.
     pub(crate) fn parse<'b>(
         &self,
         result: &mut CmdParsingResults,
         cmdline: &'b [String],
-    ) -> Result<&'b [String], String> {
-        if cmdline.is_empty() {
-            Err(format!(
-                "missing required positional argument: {}",
-                self.name
-            ))
-        } else {
-            let parsed: Box<dyn Any> = (self.parser)(&cmdline[0].clone());
-            result.add_result_value(self.name.clone(), parsed);
-            Ok(&cmdline[1..])
+    ) -> Result<&'b [String], ParseError> {
+        if cmdline.len() < self.nargs.min() {
+            return Err(ParseError::MissingPositional {
+                argument: self.name.clone(),
+                context: Box::default(),
+            });
         }
+        let count = match self.nargs.max() {
+            Some(max) => max.min(cmdline.len()),
+            None => cmdline.len(),
+        };
+        let mut values = Vec::new();
+        for (index, token) in cmdline[..count].iter().enumerate() {
+            values.push(self.convert(token, Some(index))?);
+        }
+        // only consumed tokens count as given, an empty list is not
+        let source = match count {
+            0 => ValueSource::Default,
+            _ => ValueSource::CommandLine,
+        };
+        if values.is_empty()
+            && let Some(default) = &self.default
+        {
+            values.push(self.convert(default, None)?);
+        }
+        if self.nargs.is_list() {
+            result.add_result_values(self.name.clone(), values, self.type_name);
+        } else if let Some(value) = values.pop() {
+            result.add_typed_result_value(self.name.clone(), value, self.type_name);
+        }
+        result.set_value_source(&self.name, source);
+        Ok(&cmdline[count..])
+    }
+
+    fn convert(&self, token: &String, position: Option<usize>) -> Result<Box<dyn Any>, ParseError> {
+        self.choices.check(&self.name, token, position)?;
+        (self.parser)(token).map_err(|reason| ParseError::ConversionFailed {
+            argument: self.name.clone(),
+            value: token.clone(),
+            reason,
+            position,
+            context: Box::default(),
+        })
     }
 }
 
@@ -59,11 +155,13 @@ mod tests {
         let cmd_line: &[String] = &["pos1".to_string(), "pos2".to_string()];
         let positional: PositionalArgument = PositionalArgument::new(
             "test".to_string(),
-            |x| Box::new(x.clone()),
+            |x| Ok(Box::new(x.clone())),
             "test value for unit testing".to_string(),
         );
         let mut result: CmdParsingResults = CmdParsingResults::new();
-        let remaining = positional.parse(&mut result, cmd_line)?;
+        let remaining = positional
+            .parse(&mut result, cmd_line)
+            .map_err(|e| e.to_string())?;
         if remaining == ["pos2".to_string()] {
             Ok(())
         } else {
@@ -76,7 +174,7 @@ mod tests {
         let cmd_line: &[String] = &[];
         let positional: PositionalArgument = PositionalArgument::new(
             "test".to_string(),
-            |x| Box::new(x.clone()),
+            |x| Ok(Box::new(x.clone())),
             "test value for unit testing".to_string(),
         );
         let mut result: CmdParsingResults = CmdParsingResults::new();
@@ -88,11 +186,13 @@ mod tests {
         let cmd_line: &[String] = &["42".to_string()];
         let positional: PositionalArgument = PositionalArgument::new(
             "test".to_string(),
-            |x| Box::new(x.parse::<i32>().unwrap()),
+            |x| Ok(Box::new(x.parse::<i32>().map_err(|e| e.to_string())?)),
             "test value for unit testing".to_string(),
         );
         let mut result: CmdParsingResults = CmdParsingResults::new();
-        let remaining = positional.parse(&mut result, cmd_line)?;
+        let remaining = positional
+            .parse(&mut result, cmd_line)
+            .map_err(|e| e.to_string())?;
         if remaining.is_empty() {
             if *result.get_value::<i32>("test") == 42 {
                 Ok(())
@@ -105,25 +205,31 @@ mod tests {
     }
 
     #[test]
-    #[should_panic(expected = "this should be an int")]
     fn parse_converted_bad_positional_argument() {
         let cmd_line: &[String] = &["thisisnoint".to_string()];
         let positional: PositionalArgument = PositionalArgument::new(
             "test".to_string(),
-            |x| Box::new(x.parse::<i32>().expect("this should be an int")),
+            |x| Ok(Box::new(x.parse::<i32>().map_err(|e| e.to_string())?)),
             "test value for unit testing".to_string(),
         );
         let mut result: CmdParsingResults = CmdParsingResults::new();
-        positional
-            .parse(&mut result, cmd_line)
-            .expect("expect a panic message");
+        match positional.parse(&mut result, cmd_line) {
+            Err(ParseError::ConversionFailed {
+                argument, value, ..
+            }) => {
+                assert_eq!(argument, "test");
+                assert_eq!(value, "thisisnoint");
+            }
+            Err(e) => panic!("unexpected error {:?}", e),
+            Ok(_) => panic!("should not have parsed"),
+        }
     }
 
     #[test]
     fn proper_help_msg_line() {
         let positional: PositionalArgument = PositionalArgument::new(
             "test".to_string(),
-            |x| Box::new(x.parse::<i32>().expect("this should be an int")),
+            |x| Ok(Box::new(x.parse::<i32>().map_err(|e| e.to_string())?)),
             "test value for unit testing".to_string(),
         );
         assert_eq!(
@@ -136,7 +242,7 @@ mod tests {
     fn proper_help_msg_line_longer_name() {
         let positional: PositionalArgument = PositionalArgument::new(
             "input_file".to_string(),
-            |x| Box::new(x.clone()),
+            |x| Ok(Box::new(x.clone())),
             "path to the input file".to_string(),
         );
         assert_eq!(
@@ -144,4 +250,55 @@ mod tests {
             "[input_file]          path to the input file"
         );
     }
+
+    fn string_positional(nargs: Nargs) -> PositionalArgument {
+        let mut positional = PositionalArgument::new(
+            "files".to_string(),
+            |x| Ok(Box::new(x.clone())),
+            "input files".to_string(),
+        );
+        positional.set_nargs(nargs);
+        positional
+    }
+
+    #[test]
+    fn parse_variadic_positional_collects_all_tokens() {
+        let cmd_line: &[String] = &["a".to_string(), "b".to_string()];
+        let mut result: CmdParsingResults = CmdParsingResults::new();
+        let remaining = string_positional(Nargs::ZeroOrMore)
+            .parse(&mut result, cmd_line)
+            .unwrap();
+        assert!(remaining.is_empty());
+        assert_eq!(
+            result.get_values::<String>("files"),
+            vec![&"a".to_string(), &"b".to_string()]
+        );
+    }
+
+    #[test]
+    fn parse_fixed_count_positional_misses_tokens() {
+        let cmd_line: &[String] = &["a".to_string()];
+        let mut result: CmdParsingResults = CmdParsingResults::new();
+        assert!(matches!(
+            string_positional(Nargs::Exactly(2)).parse(&mut result, cmd_line),
+            Err(ParseError::MissingPositional { .. })
+        ));
+    }
+
+    #[test]
+    fn parse_optional_positional_falls_back_to_default() {
+        let mut positional = string_positional(Nargs::Optional);
+        positional.set_default("-".to_string());
+        let mut result: CmdParsingResults = CmdParsingResults::new();
+        positional.parse(&mut result, &[]).unwrap();
+        assert_eq!(result.get_value::<String>("files"), "-");
+    }
+
+    #[test]
+    fn proper_help_msg_line_variadic() {
+        assert_eq!(
+            string_positional(Nargs::OneOrMore).help().as_str(),
+            "[files+]              input files"
+        );
+    }
 }
@@ -0,0 +1,205 @@
+use crate::parse_error::ParseError;
+use std::fs;
+use std::path::PathBuf;
+
+/// How deeply response files may name further response files.
+pub(crate) const MAX_DEPTH: usize = 8;
+
+/// Replaces every token starting with `prefix` by the tokens read from the
+/// file it names, recursively. A lone `prefix` is kept as a value.
+pub(crate) fn expand(cmdline: Vec<String>, prefix: char) -> Result<Vec<String>, ParseError> {
+    let mut expanded = Vec::new();
+    for (position, token) in cmdline.into_iter().enumerate() {
+        match response_file(&token, prefix) {
+            Some(path) => expand_file(path, prefix, position, &mut Vec::new(), &mut expanded)?,
+            None => expanded.push(token),
+        }
+    }
+    Ok(expanded)
+}
+
+fn response_file(token: &str, prefix: char) -> Option<&str> {
+    token.strip_prefix(prefix).filter(|path| !path.is_empty())
+}
+
+/// Appends the tokens of the file at `path`, named by the token at
+/// `position` of the command line, to `expanded`. `open` holds the files
+/// being expanded, to detect cycles.
+fn expand_file(
+    path: &str,
+    prefix: char,
+    position: usize,
+    open: &mut Vec<PathBuf>,
+    expanded: &mut Vec<String>,
+) -> Result<(), ParseError> {
+    let failure = |reason: String| ParseError::FromFileFailed {
+        path: path.to_string(),
+        reason,
+        position,
+        context: Box::default(),
+    };
+    if open.len() == MAX_DEPTH {
+        return Err(failure(format!(
+            "response files nested deeper than {} levels",
+            MAX_DEPTH
+        )));
+    }
+    let canonical = fs::canonicalize(path).map_err(|e| failure(e.to_string()))?;
+    if open.contains(&canonical) {
+        return Err(failure("response file includes itself".to_string()));
+    }
+    let text = fs::read_to_string(&canonical).map_err(|e| failure(e.to_string()))?;
+    let tokens = split(&text).map_err(failure)?;
+    open.push(canonical);
+    for token in tokens {
+        match response_file(&token, prefix) {
+            Some(nested) => expand_file(nested, prefix, position, open, expanded)?,
+            None => expanded.push(token),
+        }
+    }
+    open.pop();
+    Ok(())
+}
+
+/// Splits `text` into tokens like a POSIX shell: whitespace separates them,
+/// single quotes keep everything literally, double quotes and backslashes
+/// escape, and `#` at the start of a token comments out the rest of the line.
+/// Outside quotes a backslash only escapes whitespace, quotes, `#` and itself,
+/// and is kept otherwise, so Windows paths like `C:\dir\file` need no quoting.
+pub(crate) fn split(text: &str) -> Result<Vec<String>, String> {
+    let mut tokens = Vec::new();
+    let mut current: Option<String> = None;
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => tokens.extend(current.take()),
+            '#' if current.is_none() => {
+                for skipped in chars.by_ref() {
+                    if skipped == '\n' {
+                        break;
+                    }
+                }
+            }
+            '\'' => {
+                let token = current.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(quoted) => token.push(quoted),
+                        None => return Err("unterminated single quote".to_string()),
+                    }
+                }
+            }
+            '"' => {
+                let token = current.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(escaped @ ('"' | '\\' | '$' | '`')) => token.push(escaped),
+                            Some('\n') => {}
+                            Some(other) => {
+                                token.push('\\');
+                                token.push(other);
+                            }
+                            None => return Err("unterminated double quote".to_string()),
+                        },
+                        Some(quoted) => token.push(quoted),
+                        None => return Err("unterminated double quote".to_string()),
+                    }
+                }
+            }
+            '\\' => match chars.clone().next() {
+                Some('\n') => {
+                    chars.next();
+                }
+                Some(escaped) if escaped.is_whitespace() || "'\"#\\".contains(escaped) => {
+                    chars.next();
+                    current.get_or_insert_with(String::new).push(escaped);
+                }
+                _ => current.get_or_insert_with(String::new).push(c),
+            },
+            _ => current.get_or_insert_with(String::new).push(c),
+        }
+    }
+    tokens.extend(current);
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_dir::TestDir;
+
+    fn strings(tokens: &[&str]) -> Vec<String> {
+        tokens.iter().map(|token| token.to_string()).collect()
+    }
+
+    #[test]
+    fn split_like_a_shell() {
+        let text = "--name \"hello world\"\n# a comment\n-o'a b'c path\\ with\\ spaces\n\n''";
+        assert_eq!(
+            split(text),
+            Ok(strings(&[
+                "--name",
+                "hello world",
+                "-oa bc",
+                "path with spaces",
+                ""
+            ]))
+        );
+        assert_eq!(split("a#b"), Ok(strings(&["a#b"])));
+        assert_eq!(
+            split("C:\\dir\\file \\#x a\\\\b"),
+            Ok(strings(&["C:\\dir\\file", "#x", "a\\b"]))
+        );
+        assert_eq!(split("dir\\"), Ok(strings(&["dir\\"])));
+        assert!(split("\"open").is_err());
+    }
+
+    #[test]
+    fn expand_nested_files_in_place() {
+        let dir = TestDir::new("expand_nested_files_in_place");
+        let inner = dir.write("inner.args", "-v\n--out 'my file'\n");
+        let outer = dir.write("outer.args", &format!("build\n@{}\n", inner));
+        let cmdline = strings(&["tool", &format!("@{}", outer), "--", "@"]);
+        assert_eq!(
+            expand(cmdline, '@'),
+            Ok(strings(&[
+                "tool", "build", "-v", "--out", "my file", "--", "@"
+            ]))
+        );
+    }
+
+    #[test]
+    fn expand_detects_cycles() {
+        let dir = TestDir::new("expand_detects_cycles");
+        let path = dir.path("cycle.args");
+        dir.write("cycle.args", &format!("-v @{}", path));
+        let cmdline = strings(&["x", &format!("@{}", path)]);
+        match expand(cmdline, '@') {
+            Err(ParseError::FromFileFailed {
+                reason, position, ..
+            }) => {
+                assert_eq!(reason, "response file includes itself");
+                assert_eq!(position, 1);
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn expand_limits_nesting() {
+        let dir = TestDir::new("expand_limits_nesting");
+        let mut path = dir.write("depth_0.args", "-v");
+        for depth in 1..=MAX_DEPTH {
+            path = dir.write(&format!("depth_{}.args", depth), &format!("@{}", path));
+        }
+        match expand(strings(&[&format!("@{}", path)]), '@') {
+            Err(ParseError::FromFileFailed { reason, .. }) => {
+                assert_eq!(reason, "response files nested deeper than 8 levels")
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+}
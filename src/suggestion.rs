@@ -0,0 +1,69 @@
+/// Picks the candidate closest to the mistyped `input`, if any is close
+/// enough to be a likely typo: at most one edit per three characters, and at
+/// least one edit, where swapping two adjacent characters counts as one.
+pub(crate) fn suggest<'a, I>(input: &str, candidates: I) -> Option<String>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    candidates
+        .into_iter()
+        .map(|candidate| (edit_distance(input, candidate), candidate))
+        .filter(|(distance, candidate)| {
+            let longest = input.chars().count().max(candidate.chars().count());
+            *distance <= (longest / 3).max(1)
+        })
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate.to_string())
+}
+
+/// The optimal string alignment distance: insertions, deletions,
+/// substitutions and transpositions of adjacent characters.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut rows: Vec<Vec<usize>> = vec![(0..=b.len()).collect()];
+    for i in 1..=a.len() {
+        let mut row = vec![i; b.len() + 1];
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            row[j] = (rows[i - 1][j] + 1)
+                .min(row[j - 1] + 1)
+                .min(rows[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                row[j] = row[j].min(rows[i - 2][j - 2] + 1);
+            }
+        }
+        rows.push(row);
+    }
+    rows[a.len()][b.len()]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn edit_distance_counts_transposition_once() {
+        assert_eq!(edit_distance("strat", "start"), 1);
+        assert_eq!(edit_distance("stop", "start"), 3);
+        assert_eq!(edit_distance("", "list"), 4);
+    }
+
+    #[test]
+    fn suggest_picks_closest_candidate() {
+        assert_eq!(
+            suggest("strat", ["stop", "start", "status"]),
+            Some("start".to_string())
+        );
+        assert_eq!(
+            suggest("verbsoe", ["version", "verbose"]),
+            Some("verbose".to_string())
+        );
+    }
+
+    #[test]
+    fn suggest_nothing_for_unrelated_input() {
+        assert_eq!(suggest("deploy", ["start", "stop"]), None);
+        assert_eq!(suggest("x", ["list"]), None);
+    }
+}
@@ -0,0 +1,37 @@
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+
+/// A temporary directory for the files of one test, unique to the test and
+/// the process running it, removed again when dropped.
+pub(crate) struct TestDir {
+    path: PathBuf,
+}
+
+impl TestDir {
+    pub(crate) fn new(test: &str) -> TestDir {
+        let path = env::temp_dir().join(format!("rust_argparse_{}_{}", process::id(), test));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        TestDir { path }
+    }
+
+    /// The path of `file_name` in the directory.
+    pub(crate) fn path(&self, file_name: &str) -> String {
+        self.path.join(file_name).to_string_lossy().into_owned()
+    }
+
+    /// Writes `text` to `file_name` in the directory and returns its path.
+    pub(crate) fn write(&self, file_name: &str, text: &str) -> String {
+        let path = self.path(file_name);
+        fs::write(&path, text).unwrap();
+        path
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
@@ -0,0 +1,176 @@
+/// A command line token as seen by the switch matchers of optionals and
+/// flags.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Token<'a> {
+    /// `--name`, or `--name=value` with the value attached.
+    Long {
+        name: &'a str,
+        value: Option<&'a str>,
+    },
+    /// `-n`, with whatever follows the name in the same token as `rest`.
+    Short { name: char, rest: &'a str },
+    /// `-5` or `-.5`, a value unless the parser has a digit as a short name,
+    /// in which case it matches like `Short`.
+    NegativeNumber(&'a str),
+    /// `--`, after which every token is a value.
+    Separator,
+    /// Anything else, including a lone `-`.
+    Value(&'a str),
+}
+
+impl<'a> Token<'a> {
+    pub(crate) fn classify(token: &'a str) -> Token<'a> {
+        if token == "--" {
+            Token::Separator
+        } else if let Some(long) = token.strip_prefix("--") {
+            match long.split_once('=') {
+                Some((name, value)) => Token::Long {
+                    name,
+                    value: Some(value),
+                },
+                None => Token::Long {
+                    name: long,
+                    value: None,
+                },
+            }
+        } else if is_negative_number(token) {
+            Token::NegativeNumber(token)
+        } else {
+            Token::short(token).unwrap_or(Token::Value(token))
+        }
+    }
+
+    fn short(token: &'a str) -> Option<Token<'a>> {
+        let short = token.strip_prefix('-')?;
+        let name = short.chars().next()?;
+        Some(Token::Short {
+            name,
+            rest: &short[name.len_utf8()..],
+        })
+    }
+
+    /// Whether the token can be the value of an optional.
+    pub(crate) fn is_value(&self) -> bool {
+        matches!(self, Token::Value(_) | Token::NegativeNumber(_))
+    }
+
+    /// Whether the token is the switch `--long` or `-short`, with or without
+    /// an attached value.
+    pub(crate) fn matches(&self, long: &str, short: char) -> bool {
+        match self {
+            Token::Long { name, .. } => *name == long,
+            Token::Short { name, .. } => *name == short,
+            Token::NegativeNumber(number) => {
+                Token::short(number).is_some_and(|token| token.matches(long, short))
+            }
+            Token::Separator | Token::Value(_) => false,
+        }
+    }
+
+    /// The value given in the switch token itself, as in `--name=value` or
+    /// `-nvalue`.
+    pub(crate) fn attached_value(&self) -> Option<&'a str> {
+        match self {
+            Token::Long { value, .. } => *value,
+            Token::Short { rest, .. } if !rest.is_empty() => Some(rest),
+            Token::NegativeNumber(number) => {
+                Token::short(number).and_then(|token| token.attached_value())
+            }
+            Token::Short { .. } | Token::Separator | Token::Value(_) => None,
+        }
+    }
+}
+
+/// Matches like argparse's `_negative_number_matcher`, `^-\d+$|^-\d*\.\d+$`.
+fn is_negative_number(token: &str) -> bool {
+    let all_digits = |part: &str| part.chars().all(|c| c.is_ascii_digit());
+    match token.strip_prefix('-') {
+        Some(number) => match number.split_once('.') {
+            Some((integer, fraction)) => {
+                all_digits(integer) && !fraction.is_empty() && all_digits(fraction)
+            }
+            None => !number.is_empty() && all_digits(number),
+        },
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn classify_long_with_and_without_value() {
+        assert_eq!(
+            Token::classify("--timeout=30"),
+            Token::Long {
+                name: "timeout",
+                value: Some("30")
+            }
+        );
+        assert_eq!(
+            Token::classify("--timeout"),
+            Token::Long {
+                name: "timeout",
+                value: None
+            }
+        );
+        assert_eq!(
+            Token::classify("--name="),
+            Token::Long {
+                name: "name",
+                value: Some("")
+            }
+        );
+    }
+
+    #[test]
+    fn classify_short_keeps_the_rest() {
+        assert_eq!(
+            Token::classify("-t30"),
+            Token::Short {
+                name: 't',
+                rest: "30"
+            }
+        );
+        assert_eq!(Token::classify("-t").attached_value(), None);
+        assert_eq!(Token::classify("-t30").attached_value(), Some("30"));
+    }
+
+    #[test]
+    fn classify_separator_and_values() {
+        assert_eq!(Token::classify("--"), Token::Separator);
+        assert_eq!(Token::classify("-"), Token::Value("-"));
+        assert_eq!(Token::classify("file"), Token::Value("file"));
+    }
+
+    #[test]
+    fn classify_negative_numbers() {
+        assert_eq!(Token::classify("-5"), Token::NegativeNumber("-5"));
+        assert_eq!(Token::classify("-3.25"), Token::NegativeNumber("-3.25"));
+        assert_eq!(Token::classify("-.5"), Token::NegativeNumber("-.5"));
+        assert_eq!(
+            Token::classify("-5x"),
+            Token::Short {
+                name: '5',
+                rest: "x"
+            }
+        );
+        assert_eq!(
+            Token::classify("-5."),
+            Token::Short {
+                name: '5',
+                rest: "."
+            }
+        );
+        assert!(Token::classify("-5").is_value());
+    }
+
+    #[test]
+    fn negative_number_matches_digit_short() {
+        let token = Token::classify("-1");
+        assert!(token.matches("one", '1'));
+        assert!(!token.matches("five", '5'));
+        assert_eq!(Token::classify("-12").attached_value(), Some("2"));
+    }
+}